@@ -0,0 +1,187 @@
+use std;
+use std::collections::HashMap;
+use std::sync::Arc;
+use super::error::{Error, ErrorType};
+use super::async_io::{Task, ready, then, and_then, map, sleep};
+use super::async_connection::{AsyncConnection, retry};
+use super::http_action::{self, ResourceInfo};
+use super::http;
+
+/// A request that is retried, authorized, redirected and throttled like the blocking requests in
+/// `http_action`
+struct Request {
+	http_method: &'static str,
+	uri: super::URI,
+	options: Arc<super::OpenOptions>,
+	fields: HashMap<String, String>,
+	attempt: usize,
+	stale_retries: usize,
+	redirects: usize,
+	timeout_point: std::time::Instant
+}
+impl Request {
+	fn new(http_method: &'static str, uri: super::URI, options: Arc<super::OpenOptions>, timeout_point: std::time::Instant) -> Self {
+		Request{ http_method, uri, options, fields: HashMap::new(), attempt: 0, stale_retries: 0, redirects: 0, timeout_point }
+	}
+	
+	/// Builds the request-header (see `http_action::build_request`)
+	fn build(&self, proxy: Option<&super::Proxy>) -> http::RequestHeader {
+		let mut request = http_action::build_request(self.http_method, &self.uri, proxy, &self.options);
+		request.header_fields.extend(self.fields.iter().map(|(name, value)| (name.clone(), value.clone())));
+		http_action::authorize(&mut request, &self.uri, &self.options);
+		request
+	}
+	
+	/// Reestablishes `connection` unless the request was a `HEAD`-request (a response to any other
+	/// request may have a body that was not consumed)
+	fn discard(&self, connection: AsyncConnection) -> Task<Result<AsyncConnection, Error>> {
+		match self.http_method {
+			"HEAD" => ready(Ok(connection)),
+			_ => connection.reconnect(self.timeout_point)
+		}
+	}
+}
+
+/// Sends `request` and returns the final response-header (i.e. after retries, redirects,
+/// authentication-challenges and throttling-responses have been handled)
+fn send(mut request: Request, connection: AsyncConnection) -> Task<Result<(Request, AsyncConnection, http::ResponseHeader), Error>> {
+	let (header, timeout_point) = (request.build(connection.proxy()), request.timeout_point);
+	then(connection.request(header.clone(), timeout_point), move |(connection, response)| {
+		// Retry IO-errors over a new connection
+		let response = match response {
+			Ok(response) => response,
+			Err(error) => {
+				let retry_policy = request.options.retry_policy.clone();
+				return retry(&retry_policy, super::RetryOperation::Request, request.attempt, error, timeout_point, move |attempt| {
+					request.attempt = attempt;
+					and_then(connection.reconnect(timeout_point), move |connection| send(request, connection))
+				})
+			}
+		};
+		
+		// Follow redirects
+		match http_action::redirect_location(&response) {
+			Ok(Some(location)) => return redirect(request, connection, &location),
+			Ok(None) => (),
+			Err(error) => return ready(Err(error))
+		}
+		
+		// Answer authentication-challenges
+		if response.http_status_code_reason.0 == 401 {
+			if let Err(error) = request.options.auth_state.challenge(&request.options.credentials, &header, &response, &mut request.stale_retries) { return ready(Err(error)) }
+			return and_then(request.discard(connection), move |connection| send(request, connection))
+		}
+		
+		// Wait (if the deadline allows it) and use a new connection if the server is throttling the requests
+		if let Some((retry_after, cause)) = http_action::throttling(&response, &request.options) {
+			return match request.options.retry_policy.next_delay(super::RetryOperation::Request, &mut request.attempt, retry_after, &cause, timeout_point) {
+				Some(delay) => then(sleep(delay), move |_| and_then(connection.reconnect(timeout_point), move |connection| send(request, connection))),
+				None => ready(http_action::throttled_error(&cause))
+			}
+		}
+		ready(Ok((request, connection, response)))
+	})
+}
+
+/// Resolves `location` against the request-URI and sends the request to the target (see
+/// `http_action::follow_redirect`)
+fn redirect(mut request: Request, connection: AsyncConnection, location: &str) -> Task<Result<(Request, AsyncConnection, http::ResponseHeader), Error>> {
+	// Check the redirect-limit
+	let max_redirects = request.options.max_redirects;
+	if request.redirects >= max_redirects { return ready(new_err!(ErrorType::IOAccessError, format!("Too many redirects (the limit is {})", max_redirects))) }
+	request.redirects += 1;
+	
+	// Resolve location and connect if necessary
+	let target = match request.uri.resolve(location) {
+		Ok(target) => target,
+		Err(error) => return ready(Err(error))
+	};
+	let connection = if target.protocol != request.uri.protocol || target.server != request.uri.server {
+		AsyncConnection::connect(&target, &request.options, request.timeout_point)
+	} else {
+		request.discard(connection)
+	};
+	
+	// The redirected request is a new request
+	request.uri = target;
+	request.attempt = 0;
+	request.stale_retries = 0;
+	and_then(connection, move |connection| send(request, connection))
+}
+
+
+
+/// Receives the resource-information and follows redirects (see `http_action::fetch_resource_info`)
+pub fn fetch_resource_info(uri: super::URI, connection: AsyncConnection, options: Arc<super::OpenOptions>, timeout_point: std::time::Instant) -> Task<Result<(super::URI, AsyncConnection, ResourceInfo), Error>> {
+	let head = send(Request::new("HEAD", uri, options, timeout_point), connection);
+	and_then(head, move |(request, connection, response)| match http_action::parse_head_response(&response, &request.options) {
+		Ok(Some(info)) => ready(Ok((request.uri, connection, info))),
+		Ok(None) => probe_size(request, connection),
+		Err(error) => ready(Err(error))
+	})
+}
+
+/// Determines the resource-size using a ranged `GET` for the first byte (see
+/// `http_action::parse_probe_response`)
+fn probe_size(head: Request, connection: AsyncConnection) -> Task<Result<(super::URI, AsyncConnection, ResourceInfo), Error>> {
+	let mut request = Request::new("GET", head.uri, head.options, head.timeout_point);
+	request.fields.insert("Range".to_owned(), "bytes=0-0".to_owned());
+	request.redirects = head.redirects;
+	
+	and_then(send(request, connection), |(request, connection, response)| {
+		let (info, body_length) = match http_action::parse_probe_response(&response) {
+			Ok(probe) => probe,
+			Err(error) => return ready(Err(error))
+		};
+		
+		// Consume the body and reuse the connection if possible
+		let (retry_policy, timeout_point) = (request.options.retry_policy.clone(), request.timeout_point);
+		let connection = match body_length {
+			Some(length) => then(connection.body(length as usize, timeout_point), move |(connection, body)| match body {
+				Ok(_) => ready(Ok(connection)),
+				Err(ref error) if retry_policy.is_retryable(error.kind()) => connection.reconnect(timeout_point),
+				Err(error) => ready(Err(Error::from(error)))
+			}),
+			None => connection.reconnect(timeout_point)
+		};
+		map(connection, move |connection| connection.map(|connection| (request.uri, connection, info)))
+	})
+}
+
+
+
+/// Receives the `length` bytes at `file_offset` of `resource` and follows redirects (see
+/// `http_action::fetch_chunk`)
+pub fn fetch_chunk(uri: super::URI, connection: AsyncConnection, options: Arc<super::OpenOptions>, resource: ResourceInfo, file_offset: u64, length: usize, timeout_point: std::time::Instant) -> Task<Result<(super::URI, AsyncConnection, Vec<u8>), Error>> {
+	// Check length
+	if length == 0 { return ready(Ok((uri, connection, Vec::new()))) }
+	
+	// Build the range-request
+	let mut preconditions = http::RequestHeader::default();
+	let conditional = http_action::add_preconditions(&mut preconditions, &resource);
+	let mut request = Request::new("GET", uri, options, timeout_point);
+	request.fields = preconditions.header_fields;
+	request.fields.insert("Range".to_owned(), format!("bytes={}-{}", file_offset, (file_offset + length as u64) - 1));
+	receive_chunk(request, connection, Arc::new(resource), file_offset, length, conditional)
+}
+
+/// Sends the range-`request` and receives the chunk (the request is retried if the body cannot be
+/// received)
+fn receive_chunk(request: Request, connection: AsyncConnection, resource: Arc<ResourceInfo>, file_offset: u64, length: usize, conditional: bool) -> Task<Result<(super::URI, AsyncConnection, Vec<u8>), Error>> {
+	and_then(send(request, connection), move |(mut request, connection, response)| {
+		if let Err(error) = http_action::check_chunk_response(&response, &resource, file_offset, length, conditional) { return ready(Err(error)) }
+		
+		// Receive response-body
+		let timeout_point = request.timeout_point;
+		then(connection.body(length, timeout_point), move |(connection, body)| match body {
+			Ok(body) => ready(Ok((request.uri, connection, body))),
+			Err(error) => {
+				let retry_policy = request.options.retry_policy.clone();
+				retry(&retry_policy, super::RetryOperation::Request, request.attempt, error, timeout_point, move |attempt| {
+					request.attempt = attempt;
+					and_then(connection.reconnect(timeout_point), move |connection| receive_chunk(request, connection, resource, file_offset, length, conditional))
+				})
+			}
+		})
+	})
+}
@@ -0,0 +1,288 @@
+use std;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use super::error::{Error, ErrorType};
+use super::async_io::{Task, Buffered, AsyncStream, MemoryStream, ready, lazy, then, and_then, map, and_then_io, sleep, deadline};
+use super::http;
+extern crate rustls;
+extern crate tokio;
+extern crate tokio_rustls;
+
+/// Checks if `operation` should be retried after `error` and calls `f` with the attempt-counter
+/// after the backoff-delay if so (see `RetryPolicy::next_delay`)
+pub fn retry<T, F>(policy: &super::RetryPolicy, operation: super::RetryOperation, mut attempt: usize, error: std::io::Error, timeout_point: std::time::Instant, f: F) -> Task<Result<T, Error>>
+	where T: Send + 'static, F: FnOnce(usize) -> Task<Result<T, Error>> + Send + 'static
+{
+	if !policy.is_retryable(error.kind()) { return ready(Err(Error::from(error))) }
+	match policy.next_delay(operation, &mut attempt, None, &error, timeout_point) {
+		Some(delay) => then(sleep(delay), move |_| f(attempt)),
+		None => ready(Err(Error::from(error)))
+	}
+}
+
+
+
+/// The peer of an `AsyncConnection` (everything that is needed to reconnect)
+#[derive(Clone)]
+struct Peer {
+	endpoint: String,
+	server: String,
+	host: String,
+	tls_config: Option<Arc<rustls::ClientConfig>>,
+	proxy: Option<super::Proxy>,
+	retry_policy: super::RetryPolicy
+}
+
+/// The non-blocking counterpart of `Connection`
+///
+/// The operations take the connection and return it together with the result, so that it can be
+/// reconnected after an error.
+pub struct AsyncConnection {
+	peer: Peer,
+	address: std::net::SocketAddr,
+	stream: Buffered<AsyncStream>
+}
+impl AsyncConnection {
+	pub fn connect(address: &super::URI, options: &super::OpenOptions, timeout_point: std::time::Instant) -> Task<Result<Self, Error>> {
+		// Create the TLS-configuration if necessary
+		let tls_config = match address.protocol.as_str() {
+			"https" => match options.tls_client_config.get(&options.tls_config) {
+				Ok(tls_config) => Some(tls_config),
+				Err(error) => return ready(Err(error))
+			},
+			_ => None
+		};
+		
+		// Connect to the proxy instead of the server if necessary
+		let proxy = options.proxy_for(address);
+		let dial = proxy.as_ref().map(|proxy| proxy.server().to_owned()).unwrap_or_else(|| address.server.clone());
+		
+		let peer = Peer{ endpoint: address.endpoint(), server: address.server.clone(), host: address.hostname(), tls_config, proxy, retry_policy: options.retry_policy.clone() };
+		AsyncConnection::open(peer, dial, 0, timeout_point)
+	}
+	
+	pub fn reconnect(self, timeout_point: std::time::Instant) -> Task<Result<Self, Error>> {
+		// The old stream is closed when it is dropped
+		let AsyncConnection{ peer, address, .. } = self;
+		AsyncConnection::reopen(peer, address, 0, timeout_point)
+	}
+	
+	/// Returns the endpoint (see `URI::endpoint`) this connection is connected to
+	pub fn endpoint(&self) -> &str {
+		&self.peer.endpoint
+	}
+	
+	/// Returns the proxy this connection is established over (if any)
+	pub fn proxy(&self) -> Option<&super::Proxy> {
+		self.peer.proxy.as_ref()
+	}
+	
+	/// Sends `request` and receives the response-header
+	pub fn request(self, request: http::RequestHeader, timeout_point: std::time::Instant) -> Task<(Self, Result<http::ResponseHeader, std::io::Error>)> {
+		use http::{WriteableHeader, ReadableHeader};
+		
+		// Serialize request-header
+		let mut serialized = MemoryStream::default();
+		if let Err(error) = request.into_writer().write(&mut serialized, super::time_remaining(timeout_point)) { return ready((self, Err(error))) }
+		
+		// Send request-header and receive response-header
+		let exchange = self.with_stream(move |stream| and_then_io(stream.write_all(serialized.data, timeout_point), move |stream, _| {
+			stream.read_until(b"\r\n\r\n", 8192, timeout_point)
+		}));
+		
+		// Parse response-header
+		map(exchange, move |(connection, header)| {
+			let response = header.and_then(|header| {
+				let mut response = http::ResponseHeaderReader::new(8192);
+				response.read(&mut MemoryStream::new(header), super::time_remaining(timeout_point))?;
+				http::ResponseHeader::from_reader(response)
+			});
+			(connection, response)
+		})
+	}
+	
+	/// Receives a response-body with `length` bytes
+	pub fn body(self, length: usize, timeout_point: std::time::Instant) -> Task<(Self, Result<Vec<u8>, std::io::Error>)> {
+		self.with_stream(move |stream| stream.read_exact(length, timeout_point))
+	}
+	
+	/// Performs `f` on the stream
+	fn with_stream<T, F>(self, f: F) -> Task<(Self, Result<T, std::io::Error>)>
+		where T: Send + 'static, F: FnOnce(Buffered<AsyncStream>) -> Task<(Buffered<AsyncStream>, Result<T, std::io::Error>)>
+	{
+		let AsyncConnection{ peer, address, stream } = self;
+		map(f(stream), move |(stream, result)| (AsyncConnection{ peer, address, stream }, result))
+	}
+	
+	/// Resolves `dial` and connects to it until the retry-policy gives up
+	fn open(peer: Peer, dial: String, attempt: usize, timeout_point: std::time::Instant) -> Task<Result<Self, Error>> {
+		// Resolve address
+		let resolve = lazy({
+			let dial = dial.clone();
+			move || {
+				let mut lookup = Box::pin(tokio::net::lookup_host(dial));
+				Box::pin(std::future::poll_fn(move |cx| lookup.as_mut().poll(cx).map(|result| result.and_then(|mut addresses| {
+					addresses.next().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Failed to resolve address"))
+				}))))
+			}
+		});
+		
+		// Connect stream
+		let connect = and_then(deadline(resolve, timeout_point), {
+			let peer = peer.clone();
+			move |address| map(AsyncConnection::open_stream(&peer, address, timeout_point), move |stream| stream.map(|stream| (address, stream)))
+		});
+		then(connect, move |result| match result {
+			Ok((address, stream)) => ready(Ok(AsyncConnection{ peer, address, stream: Buffered::new(stream) })),
+			Err(error) => {
+				let retry_policy = peer.retry_policy.clone();
+				retry(&retry_policy, super::RetryOperation::Connect, attempt, error, timeout_point, move |attempt| AsyncConnection::open(peer, dial, attempt, timeout_point))
+			}
+		})
+	}
+	
+	/// Connects to the resolved `address` until the retry-policy gives up
+	fn reopen(peer: Peer, address: std::net::SocketAddr, attempt: usize, timeout_point: std::time::Instant) -> Task<Result<Self, Error>> {
+		then(AsyncConnection::open_stream(&peer, address, timeout_point), move |result| match result {
+			Ok(stream) => ready(Ok(AsyncConnection{ peer, address, stream: Buffered::new(stream) })),
+			Err(error) => {
+				let retry_policy = peer.retry_policy.clone();
+				retry(&retry_policy, super::RetryOperation::Reconnect, attempt, error, timeout_point, move |attempt| AsyncConnection::reopen(peer, address, attempt, timeout_point))
+			}
+		})
+	}
+	
+	/// Connects to `address`, opens the proxy-tunnel and performs the TLS-handshake if necessary
+	fn open_stream(peer: &Peer, address: std::net::SocketAddr, timeout_point: std::time::Instant) -> Task<Result<AsyncStream, std::io::Error>> {
+		let (server, host, tls_config, proxy) = (peer.server.clone(), peer.host.clone(), peer.tls_config.clone(), peer.proxy.clone());
+		let socket = deadline(lazy(move || Box::pin(tokio::net::TcpStream::connect(address))), timeout_point);
+		
+		// Open the tunnel (a HTTP-proxy forwards plain requests without tunnel)
+		let tls = tls_config.is_some();
+		let tunnel = and_then(socket, move |socket| match proxy {
+			Some(ref proxy) if tls || proxy.protocol() != super::ProxyProtocol::Http => {
+				map(tunnel(proxy.clone(), Buffered::new(socket), server, timeout_point), |(stream, result)| result.and_then(|_| stream.into_inner()))
+			},
+			_ => ready(Ok(socket))
+		});
+		
+		// Perform the TLS-handshake
+		and_then(tunnel, move |socket| match tls_config {
+			Some(tls_config) => {
+				let server_name = match rustls::ServerName::try_from(host.as_str()) {
+					Ok(server_name) => server_name,
+					Err(error) => return ready(Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, error)))
+				};
+				let handshake = tokio_rustls::TlsConnector::from(tls_config).connect(server_name, socket);
+				map(deadline(Box::pin(handshake), timeout_point), |stream| stream.map(|stream| AsyncStream::Tls(Box::new(stream))))
+			},
+			None => ready(Ok(AsyncStream::Plain(socket)))
+		})
+	}
+}
+
+/// Opens a tunnel to `target` (`host:port`) over the connection to `proxy`
+fn tunnel(proxy: super::Proxy, stream: Buffered<tokio::net::TcpStream>, target: String, timeout_point: std::time::Instant) -> Task<(Buffered<tokio::net::TcpStream>, Result<(), std::io::Error>)> {
+	match proxy.protocol() {
+		super::ProxyProtocol::Http => {
+			// Send the request and receive the response-header
+			let response = and_then_io(stream.write_all(proxy.tunnel_request(&target).into_bytes(), timeout_point), move |stream, _| {
+				stream.read_until(b"\r\n\r\n", 8192, timeout_point)
+			});
+			map(response, |(stream, header)| (stream, header.and_then(|header| super::Proxy::check_tunnel_response(&header))))
+		},
+		super::ProxyProtocol::Socks5 => {
+			// Negotiate the authentication-method and authenticate using username/password
+			let method = and_then_io(stream.write_all(proxy.socks5_greeting().to_vec(), timeout_point), move |stream, _| stream.read_exact(2, timeout_point));
+			let authenticated = and_then_io(method, move |stream, reply| {
+				match proxy.socks5_check_method(&reply).and_then(|_| proxy.socks5_auth_request()) {
+					Ok(Some(request)) => {
+						let reply = and_then_io(stream.write_all(request, timeout_point), move |stream, _| stream.read_exact(2, timeout_point));
+						map(reply, |(stream, reply)| (stream, reply.and_then(|reply| super::Proxy::socks5_check_auth(&reply))))
+					},
+					Ok(None) => ready((stream, Ok(()))),
+					Err(error) => ready((stream, Err(error)))
+				}
+			});
+			
+			// Send the connect-request, receive the reply and skip the bound address
+			let reply = and_then_io(authenticated, move |stream, _| match super::Proxy::socks5_connect_request(&target) {
+				Ok(request) => and_then_io(stream.write_all(request, timeout_point), move |stream, _| stream.read_exact(5, timeout_point)),
+				Err(error) => ready((stream, Err(error)))
+			});
+			let bound = and_then_io(reply, move |stream, reply| match super::Proxy::socks5_check_reply(&reply) {
+				Ok(remaining) => stream.read_exact(remaining, timeout_point),
+				Err(error) => ready((stream, Err(error)))
+			});
+			map(bound, |(stream, result)| (stream, result.map(|_| ())))
+		}
+	}
+}
+
+
+
+/// The non-blocking counterpart of `ConnectionPool`
+pub struct AsyncPool {
+	max_connections: usize,
+	idle: Mutex<HashMap<String, Vec<AsyncConnection>>>,
+	permits: Mutex<HashMap<String, Arc<tokio::sync::Semaphore>>>
+}
+impl AsyncPool {
+	/// Creates a new pool that opens up to `max_connections` connections per host
+	pub fn new(max_connections: usize) -> Self {
+		AsyncPool{ max_connections: std::cmp::max(max_connections, 1), idle: Mutex::new(HashMap::new()), permits: Mutex::new(HashMap::new()) }
+	}
+	
+	/// Checks out a connection to the server of `uri`, calls `f` with it and returns the connection
+	/// to the pool afterwards (or discards it if `f` failed); resolves to the URI returned by `f`
+	/// (which is updated if the request is redirected) and the result
+	///
+	/// _Note: If the connection-limit for the host is reached, this task waits until another
+	/// connection is returned or `timeout_point` is reached_
+	pub fn with_connection<T, F>(pool: &Arc<Self>, uri: super::URI, options: Arc<super::OpenOptions>, timeout_point: std::time::Instant, f: F) -> Task<Result<(super::URI, T), Error>>
+		where T: Send + 'static, F: FnOnce(super::URI, AsyncConnection) -> Task<Result<(super::URI, AsyncConnection, T), Error>> + Send + 'static
+	{
+		let (pool, endpoint) = (pool.clone(), uri.endpoint());
+		let permits = pool.permits.lock().unwrap().entry(endpoint.clone())
+			.or_insert_with(|| Arc::new(tokio::sync::Semaphore::new(pool.max_connections))).clone();
+		
+		// Wait for a permit
+		let permit = lazy(move || {
+			let mut acquire = Box::pin(permits.acquire_owned());
+			Box::pin(std::future::poll_fn(move |cx| acquire.as_mut().poll(cx).map(|permit| {
+				permit.map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "The connection-pool is closed"))
+			})))
+		});
+		let permit = then(deadline(permit, timeout_point), |permit| ready(match permit {
+			Ok(permit) => Ok(permit),
+			Err(ref error) if error.kind() == std::io::ErrorKind::TimedOut => new_err!(ErrorType::from(std::io::Error::from(std::io::ErrorKind::TimedOut)), "Timeout while waiting for a pooled connection".to_owned()),
+			Err(error) => Err(Error::from(error))
+		}));
+		
+		// Reuse an idle connection or open a new one
+		and_then(permit, move |permit| {
+			let idle = pool.idle.lock().unwrap().get_mut(&endpoint).and_then(|idle| idle.pop());
+			let connection = match idle {
+				Some(connection) => ready(Ok(connection)),
+				None => AsyncConnection::connect(&uri, &options, timeout_point)
+			};
+			
+			// The permit is released after the connection was returned (or discarded)
+			let result = and_then(connection, move |connection| f(uri, connection));
+			map(result, move |result| {
+				let result = result.map(|(uri, connection, value)| { pool.checkin(&endpoint, connection); (uri, value) });
+				drop(permit);
+				result
+			})
+		})
+	}
+	
+	fn checkin(&self, endpoint: &str, connection: AsyncConnection) {
+		// The connection might have been replaced by a connection to another host (e.g. due to a
+		// redirect); we don't pool those because they are not accounted for
+		if connection.endpoint() != endpoint { return }
+		self.idle.lock().unwrap().entry(endpoint.to_owned()).or_insert_with(Vec::new).push(connection);
+	}
+}
@@ -0,0 +1,442 @@
+use std;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use super::error::Error;
+use super::async_io::{Task, ready, lazy, then, and_then, map, blocking};
+use super::async_connection::AsyncPool;
+extern crate tokio;
+
+/// The state shared by an `AsyncFile` and it's pending reads
+#[derive(Clone)]
+struct Reader {
+	file: super::SharedFile,
+	pool: Arc<AsyncPool>,
+	options: Arc<super::OpenOptions>
+}
+impl Reader {
+	/// Reads `buffer.len()` bytes at `offset` into `buffer` and resolves to the buffer and the amount
+	/// of bytes read (which is only smaller than `buffer.len()` at the end of the resource)
+	fn read(self, buffer: Vec<u8>, offset: u64, timeout: std::time::Duration) -> Task<(Vec<u8>, Result<usize, Error>)> {
+		lazy(move || {
+			// Check if there is anything to read
+			let size = self.file.size();
+			if offset >= size || buffer.is_empty() { return ready((buffer, Ok(0))) }
+			let to_read = std::cmp::min(buffer.len() as u64, size - offset) as usize;
+			self.read_chunks(buffer, offset, 0, to_read, std::time::Instant::now() + timeout)
+		})
+	}
+	
+	/// Copies the chunks into `buffer[buffer_pos .. to_read]`
+	fn read_chunks(self, mut buffer: Vec<u8>, offset: u64, mut buffer_pos: usize, to_read: usize, timeout_point: std::time::Instant) -> Task<(Vec<u8>, Result<usize, Error>)> {
+		let chunk_size = self.file.source().chunk_size;
+		while buffer_pos < to_read {
+			let position = offset + buffer_pos as u64;
+			let aligned_offset = (position / chunk_size as u64) * chunk_size as u64;
+			let skip = (position - aligned_offset) as usize;
+			let to_copy = std::cmp::min(chunk_size - skip, to_read - buffer_pos);
+			
+			// Copy the chunk from the cache or load it
+			if !self.file.source().read_cached(aligned_offset, skip, &mut buffer[buffer_pos .. buffer_pos + to_copy]) {
+				return then(self.clone().load_chunk(aligned_offset, timeout_point), move |chunk| match chunk {
+					Ok(chunk) => {
+						buffer[buffer_pos .. buffer_pos + to_copy].copy_from_slice(&chunk[skip .. skip + to_copy]);
+						self.read_chunks(buffer, offset, buffer_pos + to_copy, to_read, timeout_point)
+					},
+					Err(error) => ready((buffer, Err(error)))
+				})
+			}
+			buffer_pos += to_copy;
+		}
+		ready((buffer, Ok(to_read)))
+	}
+	
+	/// Loads the chunk at `aligned_offset` from the disk-cache or the server and inserts it into the
+	/// caches (see `ChunkSource::load`)
+	fn load_chunk(self, aligned_offset: u64, timeout_point: std::time::Instant) -> Task<Result<Vec<u8>, Error>> {
+		let length = self.file.source().chunk_length(aligned_offset);
+		
+		// The disk-cache is accessed on the blocking thread-pool
+		let cached = match self.file.source().disk_cache.is_some() {
+			true => {
+				let file = self.file.clone();
+				blocking(move || {
+					let mut chunk = vec![0u8; length];
+					if file.source().load_from_disk(aligned_offset, &mut chunk) { Some(chunk) } else { None }
+				})
+			},
+			false => ready(Ok(None))
+		};
+		and_then(cached, move |chunk| match chunk {
+			Some(chunk) => ready(Ok(chunk)),
+			None => self.fetch_chunk(aligned_offset, length, timeout_point)
+		})
+	}
+	
+	/// Fetches the chunk at `aligned_offset`, inserts it into the caches and remembers the effective
+	/// URI
+	fn fetch_chunk(self, aligned_offset: u64, length: usize, timeout_point: std::time::Instant) -> Task<Result<Vec<u8>, Error>> {
+		let (options, resource) = (self.options.clone(), self.file.source().resource.clone());
+		let fetched = AsyncPool::with_connection(&self.pool, self.file.uri(), self.options.clone(), timeout_point, move |uri, connection| {
+			super::async_action::fetch_chunk(uri, connection, options, resource, aligned_offset, length, timeout_point)
+		});
+		map(fetched, move |fetched| fetched.map(|(uri, chunk)| {
+			self.file.set_uri(uri);
+			self.file.source().cache.lock().unwrap().insert(&chunk, aligned_offset);
+			
+			// Store the chunk in the background
+			if let Some(disk_cache) = self.file.source().disk_cache.clone() {
+				let data = chunk.clone();
+				tokio::task::spawn_blocking(move || disk_cache.store(aligned_offset, &data));
+			}
+			chunk
+		}))
+	}
+}
+
+/// A pending read (the offset and the task that reads the data into the returned read-buffer)
+struct PendingRead {
+	offset: u64,
+	task: Task<(Vec<u8>, Result<usize, Error>)>
+}
+
+/// An asynchronous handle to a HTTP-resource which implements `tokio::io::AsyncRead` and
+/// `tokio::io::AsyncSeek`
+///
+/// The chunks are fetched over non-blocking connections and share the caches with the
+/// `SharedFile`-handles returned by `shared`; only the disk-cache is accessed on tokio's blocking
+/// thread-pool. The retry-, redirect- and authentication-semantics are the same as for
+/// `SharedFile`.
+///
+/// _Note: Concurrent misses of an `AsyncFile` and a `SharedFile` for the same chunk are not
+/// deduplicated. All futures must be polled within a tokio-runtime with IO and time enabled._
+pub struct AsyncFile {
+	reader: Reader,
+	position: u64,
+	io_timeout: std::time::Duration,
+	buffer: Vec<u8>,
+	buffer_offset: u64,
+	pending: Option<PendingRead>
+}
+impl AsyncFile {
+	/// Opens a URI using `options` (see `File::open_with_options`)
+	pub fn open(uri: &str, options: &super::OpenOptions) -> Open {
+		let timeout_point = std::time::Instant::now() + options.timeout;
+		let (uri, options) = match super::File::prepare(uri, options) {
+			Ok(prepared) => prepared,
+			Err(error) => return Open{ task: ready(Err(error)) }
+		};
+		let (url, name) = (uri.url.clone(), uri.name.clone());
+		let (pool, options) = (Arc::new(AsyncPool::new(options.max_connections)), Arc::new(options));
+		
+		// Receive file-size and follow redirects
+		let options_ = options.clone();
+		let info = AsyncPool::with_connection(&pool, uri, options.clone(), timeout_point, move |uri, connection| {
+			super::async_action::fetch_resource_info(uri, connection, options_, timeout_point)
+		});
+		
+		// Opening the disk-cache accesses the filesystem
+		let task = and_then(info, move |(uri, info)| {
+			let blocking_pool = Arc::new(super::ConnectionPool::new(options.max_connections));
+			let file = blocking(move || super::File::from_resource(url, name, uri, (*options).clone(), blocking_pool, info));
+			map(file, move |file| file.and_then(|file| file).map(|file| AsyncFile::with_pool(file, pool)))
+		});
+		Open{ task }
+	}
+	
+	/// Creates an `AsyncFile` that fetches the chunks over `pool`
+	fn with_pool(file: super::File, pool: Arc<AsyncPool>) -> Self {
+		let io_timeout = file.io_timeout();
+		let file = file.into_shared();
+		let options = Arc::new(file.source().options.clone());
+		AsyncFile{ reader: Reader{ file, pool, options }, position: 0, io_timeout, buffer: Vec::new(), buffer_offset: 0, pending: None }
+	}
+	
+	/// Sets the timeout used by the `AsyncRead`-implementation
+	///
+	/// _Note: The timeout defaults to the IO-timeout of the `File`_
+	pub fn set_io_timeout(&mut self, timeout: std::time::Duration) {
+		self.io_timeout = timeout
+	}
+	
+	/// Returns the timeout used by the `AsyncRead`-implementation
+	pub fn io_timeout(&self) -> std::time::Duration {
+		self.io_timeout
+	}
+	
+	/// Returns the file-size
+	pub fn size(&self) -> u64 {
+		self.reader.file.size()
+	}
+	
+	/// Returns the file-name
+	pub fn name(&self) -> &str {
+		self.reader.file.name()
+	}
+	
+	/// Returns the current position
+	pub fn tell(&self) -> u64 {
+		self.position
+	}
+	
+	/// Returns a `SharedFile`-handle to the resource (e.g. for blocking reads)
+	pub fn shared(&self) -> super::SharedFile {
+		self.reader.file.clone()
+	}
+	
+	/// Reads `buffer.len()` bytes at `offset` into `buffer` without changing the position and
+	/// returns the amount of bytes read (see `SharedFile::read_at`)
+	pub fn read_at<'a>(&self, buffer: &'a mut[u8], offset: u64, timeout: std::time::Duration) -> ReadAt<'a> {
+		let task = self.reader.clone().read(vec![0u8; buffer.len()], offset, timeout);
+		ReadAt{ buffer, task }
+	}
+}
+impl From<super::File> for AsyncFile {
+	fn from(file: super::File) -> Self {
+		let pool = Arc::new(AsyncPool::new(file.max_connections()));
+		AsyncFile::with_pool(file, pool)
+	}
+}
+impl tokio::io::AsyncRead for AsyncFile {
+	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut tokio::io::ReadBuf) -> Poll<std::io::Result<()>> {
+		// Start a new read if the read-buffer does not contain the current position
+		let buffered = self.position >= self.buffer_offset && self.position < self.buffer_offset + self.buffer.len() as u64;
+		if !buffered && self.pending.is_none() {
+			let (position, size) = (self.position, self.size());
+			if position >= size || buf.remaining() == 0 { return Poll::Ready(Ok(())) }
+			
+			// Reuse the read-buffer
+			let mut buffer = std::mem::take(&mut self.buffer);
+			buffer.resize(buf.remaining(), 0);
+			let task = self.reader.clone().read(buffer, position, self.io_timeout);
+			self.pending = Some(PendingRead{ offset: position, task });
+		}
+		
+		// Poll the pending read
+		if !buffered {
+			let (offset, (mut buffer, result)) = match self.pending.as_mut() {
+				Some(pending) => match pending.task.as_mut().poll(cx) {
+					Poll::Ready(result) => (pending.offset, result),
+					Poll::Pending => return Poll::Pending
+				},
+				None => unreachable!("A read is always pending here")
+			};
+			self.pending = None;
+			
+			// Take back the read-buffer
+			buffer.truncate(*result.as_ref().unwrap_or(&0));
+			self.buffer = buffer;
+			self.buffer_offset = offset;
+			result?;
+		}
+		
+		// Copy the buffered data (the caller might have passed a smaller buffer this time)
+		let skip = (self.position - self.buffer_offset) as usize;
+		let to_copy = std::cmp::min(self.buffer.len() - skip, buf.remaining());
+		buf.put_slice(&self.buffer[skip .. skip + to_copy]);
+		self.position += to_copy as u64;
+		Poll::Ready(Ok(()))
+	}
+}
+impl tokio::io::AsyncSeek for AsyncFile {
+	fn start_seek(mut self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+		// Compute the absolute target-position
+		let size = self.size();
+		let target = match position {
+			std::io::SeekFrom::Start(offset) => offset as i128,
+			std::io::SeekFrom::End(by) => size as i128 + by as i128,
+			std::io::SeekFrom::Current(by) => self.position as i128 + by as i128
+		};
+		
+		// Validate boundaries and apply position (a pending read for the old position is discarded)
+		if target < 0 { return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot seek before 0")) }
+		if target > size as i128 { return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Cannot seek beyond EOF ({})", size))) }
+		if self.pending.as_ref().map(|pending| pending.offset != target as u64).unwrap_or(false) { self.pending = None }
+		self.position = target as u64;
+		Ok(())
+	}
+	
+	fn poll_complete(self: Pin<&mut Self>, _: &mut Context) -> Poll<std::io::Result<u64>> {
+		Poll::Ready(Ok(self.position))
+	}
+}
+
+
+
+/// A future that resolves to the `AsyncFile` opened by `AsyncFile::open`
+pub struct Open {
+	task: Task<Result<AsyncFile, Error>>
+}
+impl Future for Open {
+	type Output = Result<AsyncFile, Error>;
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		self.task.as_mut().poll(cx)
+	}
+}
+
+/// A future that resolves to the amount of bytes read by `AsyncFile::read_at`
+pub struct ReadAt<'a> {
+	buffer: &'a mut[u8],
+	task: Task<(Vec<u8>, Result<usize, Error>)>
+}
+impl<'a> Future for ReadAt<'a> {
+	type Output = Result<usize, Error>;
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		let (data, result) = match self.task.as_mut().poll(cx) {
+			Poll::Ready(result) => result,
+			Poll::Pending => return Poll::Pending
+		};
+		let read = result?;
+		self.buffer[.. read].copy_from_slice(&data[.. read]);
+		Poll::Ready(Ok(read))
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::AsyncFile;
+	use super::super::file::tests::{server, data, SIZE, TIMEOUT};
+	use std::future::Future;
+	use std::io::SeekFrom;
+	use std::pin::Pin;
+	use std::sync::atomic::Ordering;
+	use std::task::Poll;
+	use tokio::io::{AsyncRead, AsyncSeek};
+	
+	fn runtime() -> tokio::runtime::Runtime {
+		tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+	}
+	
+	/// Opens `url` with a cache of 4 chunks à 1000 bytes
+	fn open(runtime: &tokio::runtime::Runtime, url: &str) -> AsyncFile {
+		runtime.block_on(AsyncFile::open(url, super::super::OpenOptions::new().timeout(TIMEOUT).cache_size(4, 1000).no_proxy())).unwrap()
+	}
+	
+	/// Seeks to `position` and reads until `buffer` is full or EOF is reached
+	fn read_at(runtime: &tokio::runtime::Runtime, file: &mut AsyncFile, position: u64, buffer: &mut[u8]) -> usize {
+		Pin::new(&mut *file).start_seek(SeekFrom::Start(position)).unwrap();
+		runtime.block_on(std::future::poll_fn(|cx| {
+			let mut read_buf = tokio::io::ReadBuf::new(buffer);
+			while read_buf.remaining() > 0 {
+				let filled = read_buf.filled().len();
+				match Pin::new(&mut *file).poll_read(cx, &mut read_buf) {
+					Poll::Ready(result) => result.unwrap(),
+					Poll::Pending => return Poll::Pending
+				}
+				if read_buf.filled().len() == filled { break }
+			}
+			Poll::Ready(read_buf.filled().len())
+		}))
+	}
+	
+	#[test]
+	fn read() {
+		let _lock = super::super::cache::tests::LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let runtime = runtime();
+		let mut file = open(&runtime, &server().0);
+		assert_eq!((file.size(), file.name()), (SIZE as u64, "file.txt"));
+		
+		// Read across chunk-boundaries and beyond the cache-size
+		let mut buffer = vec![0; 1500];
+		assert_eq!(read_at(&runtime, &mut file, 0, &mut buffer), 1500);
+		assert_eq!(&buffer[..], &data()[.. 1500]);
+		
+		let mut buffer = vec![0; SIZE];
+		assert_eq!(read_at(&runtime, &mut file, 1500, &mut buffer), SIZE - 1500);
+		assert_eq!(&buffer[.. SIZE - 1500], &data()[1500 ..]);
+		assert_eq!(file.tell(), SIZE as u64);
+		assert_eq!(read_at(&runtime, &mut file, SIZE as u64, &mut buffer), 0);
+	}
+	
+	#[test]
+	fn read_buffer_reuse() {
+		let _lock = super::super::cache::tests::LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let (runtime, (url, requests)) = (runtime(), server());
+		let mut file = open(&runtime, &url);
+		
+		let mut buffer = vec![0; 2000];
+		assert_eq!(read_at(&runtime, &mut file, 0, &mut buffer), 2000);
+		let (pointer, fetched) = (file.buffer.as_ptr(), requests.load(Ordering::SeqCst));
+		
+		// A smaller read within the read-buffer is served from the read-buffer
+		let mut buffer = vec![0; 100];
+		assert_eq!(read_at(&runtime, &mut file, 500, &mut buffer), 100);
+		assert_eq!(&buffer[..], &data()[500 .. 600]);
+		assert_eq!((file.buffer_offset, file.buffer.len(), requests.load(Ordering::SeqCst)), (0, 2000, fetched));
+		
+		// The next read reuses the allocation of the read-buffer
+		let mut buffer = vec![0; 1000];
+		assert_eq!(read_at(&runtime, &mut file, 3000, &mut buffer), 1000);
+		assert_eq!(&buffer[..], &data()[3000 .. 4000]);
+		assert_eq!((file.buffer_offset, file.buffer.as_ptr()), (3000, pointer));
+	}
+	
+	#[test]
+	fn seek_while_pending() {
+		let _lock = super::super::cache::tests::LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let runtime = runtime();
+		let mut file = open(&runtime, &server().0);
+		
+		// Start a read, then seek to the same position (which keeps the pending read) and to another
+		// position (which discards it)
+		let mut buffer = vec![0; 1000];
+		runtime.block_on(std::future::poll_fn(|cx| {
+			let mut read_buf = tokio::io::ReadBuf::new(&mut buffer);
+			assert!(Pin::new(&mut file).poll_read(cx, &mut read_buf).is_pending());
+			Poll::Ready(())
+		}));
+		Pin::new(&mut file).start_seek(SeekFrom::Current(0)).unwrap();
+		assert_eq!(file.pending.as_ref().map(|pending| pending.offset), Some(0));
+		Pin::new(&mut file).start_seek(SeekFrom::End(-1000)).unwrap();
+		assert!(file.pending.is_none());
+		
+		// Read at the new position
+		assert_eq!(read_at(&runtime, &mut file, SIZE as u64 - 1000, &mut buffer), 1000);
+		assert_eq!(&buffer[..], &data()[SIZE - 1000 ..]);
+		assert_eq!(file.tell(), SIZE as u64);
+	}
+	
+	#[test]
+	fn read_at_offset() {
+		let _lock = super::super::cache::tests::LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let (runtime, (url, requests)) = (runtime(), server());
+		let file = open(&runtime, &url);
+		
+		// Read across chunk-boundaries without changing the position
+		let mut buffer = vec![0; 1500];
+		assert_eq!(runtime.block_on(file.read_at(&mut buffer, 900, TIMEOUT)).unwrap(), 1500);
+		assert_eq!(&buffer[..], &data()[900 .. 2400]);
+		assert_eq!(file.tell(), 0);
+		
+		// The chunks are shared with the blocking handles
+		let fetched = requests.load(Ordering::SeqCst);
+		let mut shared = vec![0; 1500];
+		assert_eq!(file.shared().read_at(&mut shared, 900, TIMEOUT).unwrap(), 1500);
+		assert_eq!((shared, requests.load(Ordering::SeqCst)), (buffer.clone(), fetched));
+		
+		// Read at the end of the resource
+		assert_eq!(runtime.block_on(file.read_at(&mut buffer, SIZE as u64 - 100, TIMEOUT)).unwrap(), 100);
+		assert_eq!(&buffer[.. 100], &data()[SIZE - 100 ..]);
+		assert_eq!(runtime.block_on(file.read_at(&mut buffer, SIZE as u64, TIMEOUT)).unwrap(), 0);
+	}
+	
+	#[test]
+	fn open_invalid() {
+		// Invalid parameters are reported on the first poll (which does not need a runtime)
+		let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+		let mut options = super::super::OpenOptions::new();
+		let mut open = AsyncFile::open("invalid", &options);
+		assert!(matches!(Pin::new(&mut open).poll(&mut context), Poll::Ready(Err(_))));
+		
+		options.cache_size(4, 0);
+		let mut open = AsyncFile::open("http://127.0.0.1:1/file.txt", &options);
+		assert!(matches!(Pin::new(&mut open).poll(&mut context), Poll::Ready(Err(_))));
+		
+		// Connection-errors are reported after the retries
+		let open = AsyncFile::open("http://127.0.0.1:1/file.txt", super::super::OpenOptions::new().timeout(std::time::Duration::from_secs(1)).no_proxy());
+		assert!(runtime().block_on(open).is_err());
+	}
+}
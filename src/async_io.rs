@@ -0,0 +1,295 @@
+use std;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use super::error::{Error, ErrorType};
+use super::http;
+extern crate tokio;
+extern crate tokio_rustls;
+
+/// A boxed future
+///
+/// _Note: The crate uses the 2015-edition which has no `async`-blocks, so the asynchronous
+/// operations are composed from boxed futures using `then` and `and_then`. The futures are created
+/// lazily, so they can be created outside of a tokio-runtime but must be polled within one._
+pub type Task<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Returns a task that resolves to `value`
+pub fn ready<T: Send + 'static>(value: T) -> Task<T> {
+	Box::pin(std::future::ready(value))
+}
+
+/// Returns a task that calls `f` on the first poll and resolves to the output of the returned task
+pub fn lazy<T: Send + 'static, F: FnOnce() -> Task<T> + Send + 'static>(f: F) -> Task<T> {
+	then(ready(()), move |_| f())
+}
+
+/// Returns a task that calls `f` with the output of `task` and resolves to the output of the task
+/// returned by `f`
+pub fn then<T, U, F>(task: Task<T>, f: F) -> Task<U> where T: 'static, U: 'static, F: FnOnce(T) -> Task<U> + Send + 'static {
+	Box::pin(Then{ first: Some(task), f: Some(f), second: None })
+}
+
+/// Like `then` but calls `f` only if `task` succeeded
+pub fn and_then<T, U, E, F>(task: Task<Result<T, E>>, f: F) -> Task<Result<U, E>>
+	where T: 'static, U: Send + 'static, E: Send + 'static, F: FnOnce(T) -> Task<Result<U, E>> + Send + 'static
+{
+	then(task, move |result| match result {
+		Ok(value) => f(value),
+		Err(error) => ready(Err(error))
+	})
+}
+
+/// Returns a task that resolves to `f` applied to the output of `task`
+pub fn map<T, U, F>(task: Task<T>, f: F) -> Task<U> where T: 'static, U: Send + 'static, F: FnOnce(T) -> U + Send + 'static {
+	then(task, move |value| ready(f(value)))
+}
+
+/// Like `and_then` but for operations that return their owner (e.g. a stream) together with the
+/// result
+pub fn and_then_io<S, T, U, F>(task: Task<(S, Result<T, std::io::Error>)>, f: F) -> Task<(S, Result<U, std::io::Error>)>
+	where S: Send + 'static, T: 'static, U: Send + 'static, F: FnOnce(S, T) -> Task<(S, Result<U, std::io::Error>)> + Send + 'static
+{
+	then(task, move |(owner, result)| match result {
+		Ok(value) => f(owner, value),
+		Err(error) => ready((owner, Err(error)))
+	})
+}
+
+/// Returns a task that waits for `delay`
+pub fn sleep(delay: std::time::Duration) -> Task<()> {
+	lazy(move || Box::pin(tokio::time::sleep(delay)))
+}
+
+/// Fails `task` with `std::io::ErrorKind::TimedOut` if it does not complete until `timeout_point`
+pub fn deadline<T: Send + 'static>(task: Task<Result<T, std::io::Error>>, timeout_point: std::time::Instant) -> Task<Result<T, std::io::Error>> {
+	let (mut task, mut timeout) = (task, None);
+	Box::pin(std::future::poll_fn(move |cx| match task.as_mut().poll(cx) {
+		Poll::Ready(result) => Poll::Ready(result),
+		Poll::Pending => poll_timeout(&mut timeout, timeout_point, cx).map(Err)
+	}))
+}
+
+/// Runs `f` on tokio's blocking thread-pool (e.g. for filesystem-operations)
+pub fn blocking<T: Send + 'static, F: FnOnce() -> T + Send + 'static>(f: F) -> Task<Result<T, Error>> {
+	lazy(move || {
+		let mut handle = tokio::task::spawn_blocking(f);
+		Box::pin(std::future::poll_fn(move |cx| Pin::new(&mut handle).poll(cx).map(|result| match result {
+			Ok(value) => Ok(value),
+			Err(error) => new_err!(ErrorType::Other(format!("The blocking task failed: {}", error)))
+		})))
+	})
+}
+
+/// Polls the timer for `timeout_point` (which is created on the first call because timers can only
+/// be created within a tokio-runtime)
+fn poll_timeout(timeout: &mut Option<Pin<Box<tokio::time::Sleep>>>, timeout_point: std::time::Instant, cx: &mut Context) -> Poll<std::io::Error> {
+	let timeout = timeout.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(tokio::time::Instant::from_std(timeout_point))));
+	timeout.as_mut().poll(cx).map(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))
+}
+
+/// The future returned by `then`
+struct Then<T, U, F> {
+	first: Option<Task<T>>,
+	f: Option<F>,
+	second: Option<Task<U>>
+}
+// `f` is never pinned (it is moved out before it is called)
+impl<T, U, F> Unpin for Then<T, U, F> {}
+impl<T, U, F: FnOnce(T) -> Task<U>> Future for Then<T, U, F> {
+	type Output = U;
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<U> {
+		// Poll the first task and create the second one
+		if let Some(mut first) = self.first.take() {
+			match first.as_mut().poll(cx) {
+				Poll::Ready(value) => {
+					let f = self.f.take().expect("The continuation is only called once");
+					self.second = Some(f(value))
+				},
+				Poll::Pending => { self.first = Some(first); return Poll::Pending }
+			}
+		}
+		self.second.as_mut().expect("The task is polled after completion").as_mut().poll(cx)
+	}
+}
+
+
+
+/// A plain or a TLS-stream
+pub enum AsyncStream {
+	Plain(tokio::net::TcpStream),
+	Tls(Box<tokio_rustls::client::TlsStream<tokio::net::TcpStream>>)
+}
+impl tokio::io::AsyncRead for AsyncStream {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut tokio::io::ReadBuf) -> Poll<std::io::Result<()>> {
+		match *self.get_mut() {
+			AsyncStream::Plain(ref mut stream) => Pin::new(stream).poll_read(cx, buf),
+			AsyncStream::Tls(ref mut stream) => Pin::new(&mut **stream).poll_read(cx, buf)
+		}
+	}
+}
+impl tokio::io::AsyncWrite for AsyncStream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context, data: &[u8]) -> Poll<std::io::Result<usize>> {
+		match *self.get_mut() {
+			AsyncStream::Plain(ref mut stream) => Pin::new(stream).poll_write(cx, data),
+			AsyncStream::Tls(ref mut stream) => Pin::new(&mut **stream).poll_write(cx, data)
+		}
+	}
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>> {
+		match *self.get_mut() {
+			AsyncStream::Plain(ref mut stream) => Pin::new(stream).poll_flush(cx),
+			AsyncStream::Tls(ref mut stream) => Pin::new(&mut **stream).poll_flush(cx)
+		}
+	}
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>> {
+		match *self.get_mut() {
+			AsyncStream::Plain(ref mut stream) => Pin::new(stream).poll_shutdown(cx),
+			AsyncStream::Tls(ref mut stream) => Pin::new(&mut **stream).poll_shutdown(cx)
+		}
+	}
+}
+
+
+
+/// A stream with a read-buffer (the data that is received after a header is kept for the body)
+///
+/// The operations take the stream and return it together with the result, so that the stream can
+/// be reused or reconnected after an error.
+pub struct Buffered<S> {
+	stream: S,
+	buffer: Vec<u8>
+}
+impl<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static> Buffered<S> {
+	/// Creates a new buffered stream
+	pub fn new(stream: S) -> Self {
+		Buffered{ stream, buffer: Vec::new() }
+	}
+	
+	/// Returns the stream (fails if the read-buffer contains unconsumed data)
+	pub fn into_inner(self) -> Result<S, std::io::Error> {
+		match self.buffer.is_empty() {
+			true => Ok(self.stream),
+			false => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Unexpected data received"))
+		}
+	}
+	
+	/// Writes and flushes `data`
+	pub fn write_all(self, data: Vec<u8>, timeout_point: std::time::Instant) -> Task<(Self, Result<(), std::io::Error>)> {
+		let mut data_pos = 0;
+		self.poll_with(timeout_point, move |this, cx| {
+			while data_pos < data.len() {
+				match Pin::new(&mut this.stream).poll_write(cx, &data[data_pos..]) {
+					Poll::Ready(Ok(0)) => return Poll::Ready(Err(std::io::Error::from(std::io::ErrorKind::WriteZero))),
+					Poll::Ready(Ok(written)) => data_pos += written,
+					Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+					Poll::Pending => return Poll::Pending
+				}
+			}
+			Pin::new(&mut this.stream).poll_flush(cx)
+		})
+	}
+	
+	/// Reads until `pattern` (which must occur within the first `limit` bytes) and returns the data
+	/// including `pattern`
+	pub fn read_until(self, pattern: &'static [u8], limit: usize, timeout_point: std::time::Instant) -> Task<(Self, Result<Vec<u8>, std::io::Error>)> {
+		self.poll_with(timeout_point, move |this, cx| loop {
+			let found = this.buffer.windows(pattern.len()).position(|window| window == pattern).map(|pos| pos + pattern.len());
+			match found {
+				Some(end) if end <= limit => return Poll::Ready(Ok(this.take(end))),
+				None if this.buffer.len() < limit => (),
+				_ => return Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Pattern not found within buffer")))
+			}
+			if let Err(error) = std::task::ready!(this.poll_fill(cx)) { return Poll::Ready(Err(error)) }
+		})
+	}
+	
+	/// Reads exactly `length` bytes
+	pub fn read_exact(self, length: usize, timeout_point: std::time::Instant) -> Task<(Self, Result<Vec<u8>, std::io::Error>)> {
+		self.poll_with(timeout_point, move |this, cx| loop {
+			if this.buffer.len() >= length { return Poll::Ready(Ok(this.take(length))) }
+			if let Err(error) = std::task::ready!(this.poll_fill(cx)) { return Poll::Ready(Err(error)) }
+		})
+	}
+	
+	/// Removes the first `length` bytes from the read-buffer and returns them
+	fn take(&mut self, length: usize) -> Vec<u8> {
+		let rest = self.buffer.split_off(length);
+		std::mem::replace(&mut self.buffer, rest)
+	}
+	
+	/// Receives more data into the read-buffer
+	fn poll_fill(&mut self, cx: &mut Context) -> Poll<Result<(), std::io::Error>> {
+		let mut chunk = [0u8; 16384];
+		let mut read_buf = tokio::io::ReadBuf::new(&mut chunk);
+		match Pin::new(&mut self.stream).poll_read(cx, &mut read_buf) {
+			Poll::Ready(Ok(())) if read_buf.filled().is_empty() => Poll::Ready(Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))),
+			Poll::Ready(Ok(())) => { self.buffer.extend_from_slice(read_buf.filled()); Poll::Ready(Ok(())) },
+			Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+			Poll::Pending => Poll::Pending
+		}
+	}
+	
+	/// Returns a task that polls `step` until it is ready or `timeout_point` is reached and resolves to
+	/// the stream and the result
+	fn poll_with<T, F>(self, timeout_point: std::time::Instant, mut step: F) -> Task<(Self, Result<T, std::io::Error>)>
+		where T: Send + 'static, F: FnMut(&mut Self, &mut Context) -> Poll<Result<T, std::io::Error>> + Send + 'static
+	{
+		let (mut this, mut timeout) = (Some(self), None);
+		Box::pin(std::future::poll_fn(move |cx| {
+			let result = match step(this.as_mut().expect("The task is polled after completion"), cx) {
+				Poll::Ready(result) => result,
+				Poll::Pending => match poll_timeout(&mut timeout, timeout_point, cx) {
+					Poll::Ready(error) => Err(error),
+					Poll::Pending => return Poll::Pending
+				}
+			};
+			Poll::Ready((this.take().expect("The task is polled after completion"), result))
+		}))
+	}
+}
+
+
+
+/// An in-memory-stream that is used to serialize and parse HTTP-headers with the `http`-crate
+#[derive(Default)]
+pub struct MemoryStream {
+	pub data: Vec<u8>,
+	pos: usize
+}
+impl MemoryStream {
+	/// Creates a stream that reads `data`
+	pub fn new(data: Vec<u8>) -> Self {
+		MemoryStream{ data, pos: 0 }
+	}
+}
+impl http::ReadableStream for MemoryStream {
+	fn read(&mut self, buffer: &mut[u8], buffer_pos: &mut usize, _: std::time::Duration) -> Result<(), std::io::Error> {
+		let to_read = buffer.len() - *buffer_pos;
+		if self.data.len() - self.pos < to_read { return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)) }
+		
+		buffer[*buffer_pos..].copy_from_slice(&self.data[self.pos .. self.pos + to_read]);
+		*buffer_pos += to_read;
+		self.pos += to_read;
+		Ok(())
+	}
+	
+	fn read_until(&mut self, buffer: &mut[u8], buffer_pos: &mut usize, pattern: &[u8], _: std::time::Duration) -> Result<(), std::io::Error> {
+		let length = match self.data[self.pos..].windows(pattern.len()).position(|window| window == pattern) {
+			Some(pos) => pos + pattern.len(),
+			None => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+		};
+		if length > buffer.len() - *buffer_pos { return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Pattern not found within buffer")) }
+		
+		buffer[*buffer_pos .. *buffer_pos + length].copy_from_slice(&self.data[self.pos .. self.pos + length]);
+		*buffer_pos += length;
+		self.pos += length;
+		Ok(())
+	}
+}
+impl http::WriteableStream for MemoryStream {
+	fn write(&mut self, data: &[u8], data_pos: &mut usize, _: std::time::Duration) -> Result<(), std::io::Error> {
+		self.data.extend_from_slice(&data[*data_pos..]);
+		*data_pos = data.len();
+		Ok(())
+	}
+}
@@ -0,0 +1,323 @@
+use std;
+use super::error::{Error, ErrorType};
+use super::http;
+extern crate base64;
+extern crate md5;
+extern crate sha2;
+
+/// The maximum amount of stale-nonce-retries per request
+static MAX_STALE_RETRIES: usize = 3;
+
+/// Credentials used to authenticate against the server
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Credentials {
+	/// HTTP-Basic-authentication with username and password (sent preemptively; answers
+	/// Digest-challenges, too)
+	Basic{ username: String, password: String },
+	/// HTTP-Digest-authentication with username and password (sent only after a challenge)
+	Digest{ username: String, password: String },
+	/// Bearer-token-authentication (sent preemptively)
+	Bearer(String)
+}
+impl Credentials {
+	/// Returns the username and password (if any)
+	fn username_password(&self) -> Option<(&str, &str)> {
+		match *self {
+			Credentials::Basic{ ref username, ref password } | Credentials::Digest{ ref username, ref password } => Some((username, password)),
+			Credentials::Bearer(_) => None
+		}
+	}
+}
+
+
+
+/// A Digest-challenge received via a `WWW-Authenticate`-field
+#[derive(Debug, Clone)]
+struct DigestChallenge {
+	realm: String,
+	nonce: String,
+	opaque: Option<String>,
+	algorithm: String,
+	qop_auth: bool,
+	nonce_count: u32
+}
+impl DigestChallenge {
+	/// Parses a Digest-challenge (returns `None` if `challenge` is not a supported Digest-challenge)
+	fn parse(challenge: &str) -> Option<(Self, bool)> {
+		// Check the scheme
+		let challenge = challenge.trim();
+		if !challenge.get(..7).map(|scheme| scheme.eq_ignore_ascii_case("Digest ")).unwrap_or(false) { return None }
+		
+		// Parse the comma-separated parameters (quoted values may contain commas)
+		let (mut params, mut rest) = (std::collections::HashMap::new(), challenge[7..].trim_start());
+		while !rest.is_empty() {
+			let separator = rest.find('=')?;
+			let name = rest[..separator].trim().to_ascii_lowercase();
+			rest = rest[separator + 1 ..].trim_start();
+			
+			let value = if rest.starts_with('"') {
+				let end = rest[1..].find('"')? + 1;
+				let value = rest[1..end].to_owned();
+				rest = &rest[end + 1 ..];
+				value
+			} else {
+				let end = rest.find(',').unwrap_or(rest.len());
+				let value = rest[..end].trim().to_owned();
+				rest = &rest[end..];
+				value
+			};
+			params.insert(name, value);
+			rest = rest.trim_start().trim_start_matches(',').trim_start();
+		}
+		
+		// Validate the parameters
+		let algorithm = params.get("algorithm").cloned().unwrap_or_else(|| "MD5".to_owned());
+		if !algorithm.eq_ignore_ascii_case("MD5") && !algorithm.eq_ignore_ascii_case("SHA-256") { return None }
+		let qop_auth = match params.get("qop") {
+			Some(qop) if qop.split(',').any(|qop| qop.trim() == "auth") => true,
+			Some(_) => return None,
+			None => false
+		};
+		let stale = params.get("stale").map(|stale| stale.eq_ignore_ascii_case("true")).unwrap_or(false);
+		
+		Some((DigestChallenge {
+			realm: params.remove("realm")?, nonce: params.remove("nonce")?, opaque: params.remove("opaque"),
+			algorithm, qop_auth, nonce_count: 0
+		}, stale))
+	}
+	
+	/// Computes the hex-encoded hash of `data` using the challenge's algorithm
+	fn hash(&self, data: &str) -> String {
+		use self::sha2::Digest;
+		let digest = if self.algorithm.eq_ignore_ascii_case("SHA-256") { sha2::Sha256::digest(data.as_bytes()).to_vec() }
+			else { md5::Md5::digest(data.as_bytes()).to_vec() };
+		digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+	}
+	
+	/// Creates the value of the `Authorization`-field for the next request using the client-nonce
+	/// `cnonce` (this increments the nonce-count so that the nonce can be reused across requests)
+	fn authorization(&mut self, username: &str, password: &str, http_method: &str, request_uri: &str, cnonce: &str) -> String {
+		self.nonce_count += 1;
+		
+		let ha1 = self.hash(&format!("{}:{}:{}", username, self.realm, password));
+		let ha2 = self.hash(&format!("{}:{}", http_method, request_uri));
+		let mut authorization = format!(
+			"Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}",
+			username, self.realm, self.nonce, request_uri, self.algorithm
+		);
+		
+		if self.qop_auth {
+			let nonce_count = format!("{:08x}", self.nonce_count);
+			let response = self.hash(&format!("{}:{}:{}:{}:auth:{}", ha1, self.nonce, nonce_count, cnonce, ha2));
+			authorization += &format!(", qop=auth, nc={}, cnonce=\"{}\", response=\"{}\"", nonce_count, cnonce, response);
+		} else {
+			let response = self.hash(&format!("{}:{}:{}", ha1, self.nonce, ha2));
+			authorization += &format!(", response=\"{}\"", response);
+		}
+		if let Some(ref opaque) = self.opaque { authorization += &format!(", opaque=\"{}\"", opaque) }
+		authorization
+	}
+}
+
+/// Creates a client-nonce
+fn cnonce() -> String {
+	use std::hash::{Hash, Hasher};
+	static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+	
+	let mut hasher = std::collections::hash_map::DefaultHasher::new();
+	std::time::SystemTime::now().hash(&mut hasher);
+	std::thread::current().id().hash(&mut hasher);
+	COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed).hash(&mut hasher);
+	format!("{:016x}", hasher.finish())
+}
+
+
+
+/// The authentication-state of a `File` (shared by all connections so that a Digest-nonce is
+/// reused across chunk-requests)
+#[derive(Debug, Default)]
+pub struct AuthState {
+	origin: std::sync::Mutex<Option<String>>,
+	digest: std::sync::Mutex<Option<DigestChallenge>>
+}
+impl AuthState {
+	/// Restricts the credentials to `endpoint` (they are not sent to other servers, e.g. after a
+	/// redirect)
+	pub fn set_origin(&self, endpoint: String) {
+		*self.origin.lock().unwrap() = Some(endpoint)
+	}
+	
+	/// Checks if `uri` belongs to the origin (i.e. if credentials and custom header-fields may be sent
+	/// to it)
+	pub fn is_origin(&self, uri: &super::URI) -> bool {
+		match *self.origin.lock().unwrap() {
+			Some(ref origin) => *origin == uri.endpoint(),
+			None => true
+		}
+	}
+	
+	/// Returns the value of the `Authorization`-field for a request to `uri` (if any)
+	///
+	/// _Note: `request_uri` is the request-target as sent in the request-line (which is the absolute
+	/// URL if the request is forwarded by a proxy)_
+	pub fn authorization(&self, credentials: &Option<Credentials>, uri: &super::URI, http_method: &str, request_uri: &str) -> Option<String> {
+		use self::base64::Engine;
+		
+		// Check if we are allowed to send the credentials to the server
+		let credentials = credentials.as_ref()?;
+		if !self.is_origin(uri) { return None }
+		
+		// Answer a pending Digest-challenge or authenticate preemptively
+		if let (Some(digest), Some((username, password))) = (self.digest.lock().unwrap().as_mut(), credentials.username_password()) {
+			return Some(digest.authorization(username, password, http_method, request_uri, &cnonce()))
+		}
+		match *credentials {
+			Credentials::Basic{ ref username, ref password } => {
+				let encoded = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+				Some(format!("Basic {}", encoded))
+			},
+			Credentials::Digest{ .. } => None,
+			Credentials::Bearer(ref token) => Some(format!("Bearer {}", token))
+		}
+	}
+	
+	/// Processes a `401`-response to `request`; returns `Ok(())` if the request should be retried
+	/// with a new `Authorization`-field
+	///
+	/// _Note: `stale_retries` counts the stale-nonce-retries of the request; the request fails if the
+	/// server keeps rejecting fresh nonces as stale_
+	pub fn challenge(&self, credentials: &Option<Credentials>, request: &http::RequestHeader, response: &http::ResponseHeader, stale_retries: &mut usize) -> Result<(), Error> {
+		let challenge = response.header_fields.get("WWW-Authenticate").and_then(|challenge| DigestChallenge::parse(challenge));
+		let answered = request.header_fields.get("Authorization").map(|authorization| authorization.starts_with("Digest ")).unwrap_or(false);
+		self.accept_challenge(credentials, challenge, answered, stale_retries)
+	}
+	
+	/// Stores the parsed `challenge` (and it's stale-flag) if it can be answered; `answered` indicates
+	/// whether the rejected request already contained a Digest-response
+	fn accept_challenge(&self, credentials: &Option<Credentials>, challenge: Option<(DigestChallenge, bool)>, answered: bool, stale_retries: &mut usize) -> Result<(), Error> {
+		// Check if we can answer the challenge
+		if credentials.as_ref().and_then(|credentials| credentials.username_password()).is_none() {
+			throw_err!(ErrorType::AuthenticationFailed, match *credentials {
+				Some(_) => "The server rejected the credentials".to_owned(),
+				None => "The server requires authentication".to_owned()
+			})
+		}
+		let (challenge, stale) = match challenge {
+			Some(challenge) => challenge,
+			None => throw_err!(ErrorType::AuthenticationFailed, "The server rejected the credentials".to_owned())
+		};
+		
+		// A rejected Digest-response is only retried if the nonce was stale
+		if answered && !stale { throw_err!(ErrorType::AuthenticationFailed, "The server rejected the credentials".to_owned()) }
+		if answered {
+			if *stale_retries >= MAX_STALE_RETRIES { throw_err!(ErrorType::AuthenticationFailed, "The server keeps rejecting the nonce as stale".to_owned()) }
+			*stale_retries += 1;
+		}
+		
+		*self.digest.lock().unwrap() = Some(challenge);
+		Ok(())
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::{Credentials, DigestChallenge, AuthState, MAX_STALE_RETRIES};
+	use super::super::URI;
+	
+	/// The challenge from RFC 7616, section 3.9.1
+	const CHALLENGE: &str = "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=MD5, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+	const CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+	
+	/// Extracts the value of the parameter `name` from an `Authorization`-field
+	fn param<'a>(authorization: &'a str, name: &str) -> &'a str {
+		let start = authorization.find(&format!(" {}=", name)).unwrap() + name.len() + 2;
+		let value = &authorization[start..];
+		match value.starts_with('"') {
+			true => &value[1 .. value[1..].find('"').unwrap() + 1],
+			false => &value[.. value.find(',').unwrap_or(value.len())]
+		}
+	}
+	
+	fn credentials() -> Option<Credentials> {
+		Some(Credentials::Digest{ username: "Mufasa".to_owned(), password: "Circle of Life".to_owned() })
+	}
+	
+	#[test]
+	fn parse() {
+		let (challenge, stale) = DigestChallenge::parse(CHALLENGE).unwrap();
+		assert_eq!((challenge.realm.as_str(), challenge.nonce.as_str()), ("http-auth@example.org", "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v"));
+		assert_eq!(challenge.opaque.as_deref(), Some("FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS"));
+		assert_eq!((challenge.algorithm.as_str(), challenge.qop_auth, stale), ("MD5", true, false));
+		
+		// Quoted values may contain commas and the parameter-names are case-insensitive
+		let (challenge, stale) = DigestChallenge::parse("digest Realm=\"a, b\",nonce=n , STALE=TRUE").unwrap();
+		assert_eq!((challenge.realm.as_str(), challenge.nonce.as_str(), challenge.opaque, stale), ("a, b", "n", None, true));
+		assert_eq!((challenge.algorithm.as_str(), challenge.qop_auth), ("MD5", false));
+	}
+	
+	#[test]
+	fn parse_invalid() {
+		let challenges = [
+			"", "Digest", "Basic realm=\"a\"", "Digest nonce=\"n\"", "Digest realm=\"a\"", "Digest realm=\"a, nonce=n",
+			"Digest realm=a, nonce=n, algorithm=SHA-512", "Digest realm=a, nonce=n, qop=auth-int", "Digest\u{e4} realm=a, nonce=n",
+			"Dig\u{e4}st realm=a, nonce=n"
+		];
+		for challenge in challenges.iter() { assert!(DigestChallenge::parse(challenge).is_none(), "{}", challenge) }
+	}
+	
+	#[test]
+	fn response_md5() {
+		// The example from RFC 7616, section 3.9.1
+		let (mut challenge, _) = DigestChallenge::parse(CHALLENGE).unwrap();
+		let authorization = challenge.authorization("Mufasa", "Circle of Life", "GET", "/dir/index.html", CNONCE);
+		assert_eq!(param(&authorization, "response"), "8ca523f5e9506fed4657c9700eebdbec");
+		assert_eq!((param(&authorization, "nc"), param(&authorization, "qop"), param(&authorization, "uri")), ("00000001", "auth", "/dir/index.html"));
+		assert_eq!(param(&authorization, "opaque"), "FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS");
+	}
+	
+	#[test]
+	fn response_sha256() {
+		// The example from RFC 7616, section 3.9.1
+		let (mut challenge, _) = DigestChallenge::parse(&CHALLENGE.replace("MD5", "SHA-256")).unwrap();
+		let authorization = challenge.authorization("Mufasa", "Circle of Life", "GET", "/dir/index.html", CNONCE);
+		assert_eq!(param(&authorization, "response"), "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1");
+		assert_eq!(param(&authorization, "algorithm"), "SHA-256");
+	}
+	
+	#[test]
+	fn nonce_count() {
+		let (state, uri, mut stale_retries) = (AuthState::default(), URI::parse("http://example.org/file").unwrap(), 0);
+		assert_eq!(state.authorization(&credentials(), &uri, "GET", "/file"), None);
+		
+		// The nonce is reused with an incremented nonce-count
+		state.accept_challenge(&credentials(), DigestChallenge::parse(CHALLENGE), false, &mut stale_retries).unwrap();
+		assert_eq!(param(&state.authorization(&credentials(), &uri, "GET", "/file").unwrap(), "nc"), "00000001");
+		assert_eq!(param(&state.authorization(&credentials(), &uri, "GET", "/file").unwrap(), "nc"), "00000002");
+		
+		// A new nonce resets the nonce-count
+		let stale = CHALLENGE.replace("7ypf", "abcd") + ", stale=true";
+		state.accept_challenge(&credentials(), DigestChallenge::parse(&stale), true, &mut stale_retries).unwrap();
+		let authorization = state.authorization(&credentials(), &uri, "GET", "/file").unwrap();
+		assert_eq!((param(&authorization, "nc"), param(&authorization, "nonce")), ("00000001", "abcd/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v"));
+		assert_eq!(stale_retries, 1);
+	}
+	
+	#[test]
+	fn stale() {
+		let (state, mut stale_retries) = (AuthState::default(), 0);
+		let stale = CHALLENGE.to_owned() + ", stale=true";
+		
+		// A rejected response is only retried if the nonce was stale and only a limited number of times
+		assert!(state.accept_challenge(&credentials(), DigestChallenge::parse(CHALLENGE), true, &mut stale_retries).is_err());
+		for _ in 0..MAX_STALE_RETRIES {
+			state.accept_challenge(&credentials(), DigestChallenge::parse(&stale), true, &mut stale_retries).unwrap();
+		}
+		assert!(state.accept_challenge(&credentials(), DigestChallenge::parse(&stale), true, &mut stale_retries).is_err());
+		
+		// A challenge cannot be answered without username and password
+		assert!(state.accept_challenge(&None, DigestChallenge::parse(CHALLENGE), false, &mut 0).is_err());
+		assert!(state.accept_challenge(&Some(Credentials::Bearer("token".to_owned())), DigestChallenge::parse(CHALLENGE), false, &mut 0).is_err());
+	}
+}
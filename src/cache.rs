@@ -1,5 +1,50 @@
 use std;
 use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The amount of bytes allocated by all caches in this process
+static RESIDENT_SIZE: AtomicUsize = AtomicUsize::new(0);
+/// The process-wide memory-budget for all caches
+static MEMORY_BUDGET: AtomicUsize = AtomicUsize::new(std::usize::MAX);
+
+/// Sets the process-wide memory-budget for all caches
+///
+/// If the budget is exhausted, caches reuse their least-recently-used chunks instead of allocating
+/// new ones and adaptive caches (see `CachePolicy`) shrink
+pub fn set_cache_memory_budget(budget: usize) {
+	MEMORY_BUDGET.store(budget, Ordering::SeqCst)
+}
+
+/// Returns the amount of memory allocated by all caches in this process
+pub fn cache_memory_usage() -> usize {
+	RESIDENT_SIZE.load(Ordering::SeqCst)
+}
+
+
+
+/// A policy for adaptive cache-resizing
+///
+/// Every `evaluation_interval` accesses the cache evaluates it's miss-rate: If the process-wide
+/// memory-budget is exceeded, the chunk-count is halved; otherwise, if the miss-rate exceeds
+/// `grow_miss_rate` and the additional chunks fit into the memory-budget, the chunk-count is
+/// doubled. The chunk-count always stays within `min_chunk_count` and `max_chunk_count`.
+#[derive(Debug, Clone, Copy)]
+pub struct CachePolicy {
+	/// The minimum chunk-count
+	pub min_chunk_count: usize,
+	/// The maximum chunk-count
+	pub max_chunk_count: usize,
+	/// The amount of accesses after which the hit-rate is evaluated
+	pub evaluation_interval: usize,
+	/// The miss-rate (`0.0` to `1.0`) above which the cache grows
+	pub grow_miss_rate: f64
+}
+impl Default for CachePolicy {
+	fn default() -> Self {
+		CachePolicy{ min_chunk_count: 16, max_chunk_count: 8192, evaluation_interval: 256, grow_miss_rate: 0.25 }
+	}
+}
+
 
 struct Entry {
 	pub data: Vec<u8>,
@@ -9,32 +54,32 @@ struct Entry {
 
 pub struct CacheDB {
 	time_null: std::time::Instant,
+	chunk_count: usize,
+	chunk_size: usize,
 	entries: Vec<Entry>,
 	accessed: BTreeMap<u64, usize>,
-	offset: HashMap<u64, usize>
+	offset: HashMap<u64, usize>,
+	
+	policy: Option<CachePolicy>,
+	hits: usize,
+	misses: usize
 }
 impl CacheDB {
+	/// Creates a new cache with up to `chunk_count` chunks
+	///
+	/// _Note: The chunks are allocated on demand_
 	pub fn new(chunk_count: usize, chunk_size: usize) -> Self {
-		// Create and initialize entries and accessed-map
-		let (mut entries, mut accessed) = (Vec::with_capacity(chunk_count), BTreeMap::new());
-		for i in 0..chunk_count {
-			// Allocate chunk
-			let mut chunk = vec![0u8; chunk_size];
-			chunk.reserve_exact(chunk_size);
-			
-			// Insert entries
-			entries.push(Entry{ data: chunk, offset: std::u64::MAX, timestamp: i as u64 });
-			accessed.insert(i as u64, i);
+		CacheDB {
+			time_null: std::time::Instant::now(), chunk_count, chunk_size, entries: Vec::new(), accessed: BTreeMap::new(), offset: HashMap::new(),
+			policy: None, hits: 0, misses: 0
 		}
-		
-		CacheDB{ time_null: std::time::Instant::now(), entries, accessed, offset: HashMap::new() }
 	}
 	
 	fn update_timestamp(&mut self, index: usize) {
 		let entry: &Entry = self.entries.get(index).unwrap();
 		
 		// Update entry in `accessed`
-		self.accessed.remove(&entry.timestamp);
+		if self.accessed.get(&entry.timestamp) == Some(&index) { self.accessed.remove(&entry.timestamp); }
 		
 		// Make sure we don't overwrite a cache-entry
 		loop {
@@ -44,23 +89,115 @@ impl CacheDB {
 			};
 			if !self.accessed.contains_key(&timestamp) {
 				self.accessed.insert(timestamp, index);
+				self.entries[index].timestamp = timestamp;
 				break
 			}
 		}
 	}
 	
+	fn remove_entry(&mut self, index: usize) {
+		// Remove the entry from the maps
+		let (timestamp, offset) = (self.entries[index].timestamp, self.entries[index].offset);
+		self.accessed.remove(&timestamp);
+		if self.offset.get(&offset) == Some(&index) { self.offset.remove(&offset); }
+		
+		// Remove the entry and update the index of the entry that took it's place
+		self.entries.swap_remove(index);
+		RESIDENT_SIZE.fetch_sub(self.chunk_size, Ordering::SeqCst);
+		if let Some(moved) = self.entries.get(index) {
+			self.accessed.insert(moved.timestamp, index);
+			self.offset.insert(moved.offset, index);
+		}
+	}
+	
 	pub fn insert(&mut self, data: &[u8], offset: u64) {
-		// Get oldest entry and remove it from `offset`
-		let oldest_entry = *self.accessed.iter().next().unwrap().1;
-		self.offset.remove(&self.entries[oldest_entry].offset);
+		if self.chunk_count == 0 { return }
+		
+		// Get the entry for the chunk (the chunk might have been fetched concurrently), allocate a
+		// new entry (if the memory-budget allows it) or reuse the oldest entry
+		let within_budget = RESIDENT_SIZE.load(Ordering::SeqCst) + self.chunk_size <= MEMORY_BUDGET.load(Ordering::SeqCst);
+		let index = if let Some(index) = self.offset.get(&offset).cloned() { index }
+			else if self.entries.len() < self.chunk_count && (within_budget || self.entries.is_empty()) {
+				self.entries.push(Entry{ data: vec![0u8; self.chunk_size], offset, timestamp: std::u64::MAX });
+				RESIDENT_SIZE.fetch_add(self.chunk_size, Ordering::SeqCst);
+				self.entries.len() - 1
+			} else {
+				let oldest_entry = *self.accessed.iter().next().unwrap().1;
+				self.offset.remove(&self.entries[oldest_entry].offset);
+				oldest_entry
+			};
 		
-		// Update oldest entry
-		&(self.entries[oldest_entry].data)[.. data.len()].copy_from_slice(data);
-		self.entries[oldest_entry].offset = offset;
+		// Update entry
+		self.entries[index].data[.. data.len()].copy_from_slice(data);
+		self.entries[index].offset = offset;
 		
 		// Reinsert/update entry
-		self.offset.insert(offset, oldest_entry);
-		self.update_timestamp(oldest_entry);
+		self.offset.insert(offset, index);
+		self.update_timestamp(index);
+	}
+	
+	/// Sets the maximum chunk-count and releases the least-recently-used chunks if the cache is
+	/// shrunk
+	pub fn set_chunk_count(&mut self, chunk_count: usize) {
+		self.chunk_count = chunk_count;
+		while self.entries.len() > chunk_count {
+			let oldest_entry = *self.accessed.iter().next().unwrap().1;
+			self.remove_entry(oldest_entry);
+		}
+		self.entries.shrink_to_fit();
+	}
+	
+	/// Returns the maximum chunk-count
+	pub fn chunk_count(&self) -> usize {
+		self.chunk_count
+	}
+	
+	/// Sets the policy for adaptive resizing (`None` disables adaptive resizing)
+	pub fn set_policy(&mut self, policy: Option<CachePolicy>) {
+		self.policy = policy;
+		self.hits = 0;
+		self.misses = 0;
+		if let Some(policy) = policy {
+			let chunk_count = std::cmp::max(std::cmp::min(self.chunk_count, policy.max_chunk_count), policy.min_chunk_count);
+			self.set_chunk_count(chunk_count)
+		}
+	}
+	
+	/// Returns the policy for adaptive resizing
+	pub fn policy(&self) -> Option<CachePolicy> {
+		self.policy
+	}
+	
+	/// Records a cache-access and resizes the cache according to the policy (if any)
+	pub fn record_access(&mut self, hit: bool) {
+		let policy = if let Some(policy) = self.policy { policy }
+			else { return };
+		
+		// Update the statistics
+		if hit { self.hits += 1 } else { self.misses += 1 }
+		if self.hits + self.misses < policy.evaluation_interval { return }
+		let (hits, misses) = (std::mem::replace(&mut self.hits, 0), std::mem::replace(&mut self.misses, 0));
+		
+		// Compute and apply the new chunk-count
+		let (resident, budget) = (RESIDENT_SIZE.load(Ordering::SeqCst), MEMORY_BUDGET.load(Ordering::SeqCst));
+		let growth = self.chunk_count.saturating_mul(self.chunk_size);
+		let chunk_count = if resident > budget { self.chunk_count / 2 }
+			else if misses as f64 / (hits + misses) as f64 > policy.grow_miss_rate && resident.saturating_add(growth) <= budget { self.chunk_count.saturating_mul(2) }
+			else { self.chunk_count };
+		self.set_chunk_count(std::cmp::max(std::cmp::min(chunk_count, policy.max_chunk_count), policy.min_chunk_count));
+	}
+	
+	/// Discards all cached chunks and releases their memory
+	pub fn clear(&mut self) {
+		RESIDENT_SIZE.fetch_sub(self.resident_size(), Ordering::SeqCst);
+		self.entries = Vec::new();
+		self.accessed.clear();
+		self.offset.clear();
+	}
+	
+	/// Returns the amount of bytes currently allocated for chunks
+	pub fn resident_size(&self) -> usize {
+		self.entries.len() * self.chunk_size
 	}
 	
 	pub fn contains(&self, offset: u64) -> bool {
@@ -77,3 +214,105 @@ impl CacheDB {
 		&self.entries.get(index).unwrap().data
 	}
 }
+
+impl Drop for CacheDB {
+	fn drop(&mut self) {
+		RESIDENT_SIZE.fetch_sub(self.resident_size(), Ordering::SeqCst);
+	}
+}
+
+
+
+#[cfg(test)]
+pub mod tests {
+	use super::{CacheDB, CachePolicy, MEMORY_BUDGET, RESIDENT_SIZE};
+	use std::sync::atomic::Ordering;
+	
+	/// Serializes the tests that allocate chunks because the memory-budget and the resident size are
+	/// process-wide
+	pub static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+	
+	const POLICY: CachePolicy = CachePolicy{ min_chunk_count: 2, max_chunk_count: 16, evaluation_interval: 4, grow_miss_rate: 0.25 };
+	
+	/// Records `hits` hits and `misses` misses
+	fn record(cache: &mut CacheDB, hits: usize, misses: usize) {
+		for _ in 0..hits { cache.record_access(true) }
+		for _ in 0..misses { cache.record_access(false) }
+	}
+	
+	#[test]
+	fn lru() {
+		let _lock = LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let mut cache = CacheDB::new(2, 4);
+		cache.insert(&[1; 4], 0);
+		cache.insert(&[2; 4], 4);
+		assert_eq!(cache.get(0), &[1; 4]);
+		
+		// The least-recently-used chunk is replaced
+		cache.insert(&[3; 4], 8);
+		assert!(cache.contains(0) && !cache.contains(4) && cache.contains(8));
+		assert_eq!(cache.resident_size(), 8);
+		
+		// Shrinking keeps the most-recently-used chunks
+		cache.set_chunk_count(1);
+		assert!(!cache.contains(0) && cache.contains(8));
+		assert_eq!((cache.chunk_count(), cache.resident_size()), (1, 4));
+	}
+	
+	#[test]
+	fn grow() {
+		let _lock = LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let mut cache = CacheDB::new(2, 4);
+		cache.set_policy(Some(POLICY));
+		
+		// A cold cache (100% misses) and a cache which is slightly too small grow
+		record(&mut cache, 0, 4);
+		assert_eq!(cache.chunk_count(), 4);
+		record(&mut cache, 1, 3);
+		assert_eq!(cache.chunk_count(), 8);
+		
+		// The chunk-count is only evaluated every `evaluation_interval` accesses and is limited to
+		// `max_chunk_count`
+		record(&mut cache, 0, 3);
+		assert_eq!(cache.chunk_count(), 8);
+		record(&mut cache, 0, 1);
+		record(&mut cache, 0, 4);
+		record(&mut cache, 0, 4);
+		assert_eq!(cache.chunk_count(), 16);
+	}
+	
+	#[test]
+	fn keep() {
+		let _lock = LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let mut cache = CacheDB::new(4, 4);
+		cache.set_policy(Some(POLICY));
+		
+		// A miss-rate up to `grow_miss_rate` keeps the chunk-count
+		record(&mut cache, 4, 0);
+		record(&mut cache, 3, 1);
+		assert_eq!(cache.chunk_count(), 4);
+	}
+	
+	#[test]
+	fn budget() {
+		let _lock = LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let mut cache = CacheDB::new(8, 4);
+		cache.set_policy(Some(POLICY));
+		cache.insert(&[1; 4], 0);
+		
+		// The cache does not grow beyond the budget
+		MEMORY_BUDGET.store(RESIDENT_SIZE.load(Ordering::SeqCst), Ordering::SeqCst);
+		record(&mut cache, 0, 4);
+		assert_eq!(cache.chunk_count(), 8);
+		
+		// The cache shrinks if the budget is exceeded (but not below `min_chunk_count`)
+		MEMORY_BUDGET.store(0, Ordering::SeqCst);
+		record(&mut cache, 4, 0);
+		assert_eq!(cache.chunk_count(), 4);
+		record(&mut cache, 4, 0);
+		record(&mut cache, 4, 0);
+		assert_eq!(cache.chunk_count(), 2);
+		
+		MEMORY_BUDGET.store(usize::MAX, Ordering::SeqCst);
+	}
+}
@@ -2,31 +2,74 @@ use std;
 use super::error::{Error, ErrorType};
 extern crate network_io;
 extern crate http;
+extern crate rustls;
+
+/// A transport-layer (e.g. plain TCP or TLS) that can be used by a `Connection`
+pub trait Transport: Send {
+	fn read(&mut self, buffer: &mut[u8], buffer_pos: &mut usize, timeout: std::time::Duration) -> Result<(), std::io::Error>;
+	fn read_until(&mut self, buffer: &mut[u8], buffer_pos: &mut usize, pattern: &[u8], timeout: std::time::Duration) -> Result<(), std::io::Error>;
+	fn write(&mut self, data: &[u8], data_pos: &mut usize, timeout: std::time::Duration) -> Result<(), std::io::Error>;
+	fn shutdown(&mut self);
+}
+impl Transport for network_io::TcpStream {
+	fn read(&mut self, buffer: &mut[u8], buffer_pos: &mut usize, timeout: std::time::Duration) -> Result<(), std::io::Error> {
+		network_io::TcpStream::read(self, buffer, buffer_pos, timeout)
+	}
+	fn read_until(&mut self, buffer: &mut[u8], buffer_pos: &mut usize, pattern: &[u8], timeout: std::time::Duration) -> Result<(), std::io::Error> {
+		network_io::TcpStream::read_until(self, buffer, buffer_pos, pattern, timeout)
+	}
+	fn write(&mut self, data: &[u8], data_pos: &mut usize, timeout: std::time::Duration) -> Result<(), std::io::Error> {
+		network_io::TcpStream::write(self, data, data_pos, timeout)
+	}
+	fn shutdown(&mut self) {
+		let _ = network_io::TcpStream::shutdown(self, std::net::Shutdown::Both);
+	}
+}
+
+
 
 pub struct Connection {
+	endpoint: String,
 	address: std::net::SocketAddr,
-	stream: network_io::TcpStream
+	server: String,
+	host: String,
+	tls_config: Option<std::sync::Arc<rustls::ClientConfig>>,
+	proxy: Option<super::Proxy>,
+	retry_policy: super::RetryPolicy,
+	stream: Box<dyn Transport>
 }
 impl Connection {
-	pub fn connect(address: &super::URI, timeout: std::time::Duration) -> Result<Self, Error> {
+	pub fn connect(address: &super::URI, options: &super::OpenOptions, timeout: std::time::Duration) -> Result<Self, Error> {
 		let timeout_point = std::time::Instant::now() + timeout;
 		
+		// Create the TLS-configuration if necessary
+		let (server, host) = (address.server.clone(), address.hostname());
+		let tls_config = match address.protocol.as_str() {
+			"https" => Some(options.tls_client_config.get(&options.tls_config)?),
+			_ => None
+		};
+		
+		// Connect to the proxy instead of the server if necessary
+		let proxy = options.proxy_for(address);
+		let dial = proxy.as_ref().map(|proxy| proxy.server().to_owned()).unwrap_or_else(|| server.clone());
+		
 		// Try to resolve and connect until the timeout is reached
+		let mut retrier = options.retry_policy.retrier(super::RetryOperation::Connect);
 		'retry_loop: while super::time_remaining(timeout_point) > std::time::Duration::default() {
 			// Resolve address
-			let address = match network_io::resolver::resolve_address(&address.server, super::time_remaining(timeout_point)) {
+			let socket_address = match network_io::resolver::resolve_address(&dial, super::time_remaining(timeout_point)) {
 				Ok(address) => address,
-				Err(ref error) if Connection::is_recoverable(error) => continue 'retry_loop,
-				Err(error) => throw_err!(ErrorType::from(error))
+				Err(ref error) if retrier.retry(error, timeout_point) => continue 'retry_loop,
+				Err(error) => return Err(Error::from(error))
 			};
 			
-			// Connect TCP-stream
-			let stream = match network_io::TcpStream::connect(address, super::time_remaining(timeout_point)) {
+			// Connect stream
+			let stream = match Connection::open_stream(socket_address, &server, &host, &tls_config, &proxy, super::time_remaining(timeout_point)) {
 				Ok(stream) => stream,
-				Err(ref error) if Connection::is_recoverable(error) => continue 'retry_loop,
-				Err(error) => throw_err!(ErrorType::from(error))
+				Err(ref error) if retrier.retry(error, timeout_point) => continue 'retry_loop,
+				Err(error) => return Err(Error::from(error))
 			};
-			return Ok(Connection{ address, stream })
+			return Ok(Connection{ endpoint: address.endpoint(), address: socket_address, server, host, tls_config, proxy, retry_policy: options.retry_policy.clone(), stream })
 		}
 		throw_err!(ErrorType::from(std::io::Error::from(std::io::ErrorKind::TimedOut)))
 	}
@@ -35,25 +78,43 @@ impl Connection {
 		let timeout_point = std::time::Instant::now() + timeout;
 		
 		// Try to shutdown the socket
-		let _ = self.stream.shutdown(std::net::Shutdown::Both);
+		self.stream.shutdown();
 		
 		// Try to reconnect
+		let retry_policy = self.retry_policy.clone();
+		let mut retrier = retry_policy.retrier(super::RetryOperation::Reconnect);
 		'retry_loop: while super::time_remaining(timeout_point) > std::time::Duration::default() {
-			self.stream = match network_io::TcpStream::connect(self.address, timeout) {
+			self.stream = match Connection::open_stream(self.address, &self.server, &self.host, &self.tls_config, &self.proxy, super::time_remaining(timeout_point)) {
 				Ok(stream) => stream,
-				Err(ref error) if Connection::is_recoverable(error) => continue 'retry_loop,
-				Err(error) => throw_err!(ErrorType::from(error))
+				Err(ref error) if retrier.retry(error, timeout_point) => continue 'retry_loop,
+				Err(error) => return Err(Error::from(error))
 			};
 			return Ok(())
 		}
 		throw_err!(ErrorType::from(std::io::Error::from(std::io::ErrorKind::TimedOut)))
 	}
 	
-	pub fn is_recoverable(error: &std::io::Error) -> bool {
-		match error.kind() {
-			std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::UnexpectedEof => true,
-			_ => false
-		}
+	/// Returns the endpoint (see `URI::endpoint`) this connection is connected to
+	pub fn endpoint(&self) -> &str {
+		&self.endpoint
+	}
+	
+	/// Returns the proxy this connection is established over (if any)
+	pub fn proxy(&self) -> Option<&super::Proxy> {
+		self.proxy.as_ref()
+	}
+	
+	fn open_stream(address: std::net::SocketAddr, server: &str, host: &str, tls_config: &Option<std::sync::Arc<rustls::ClientConfig>>, proxy: &Option<super::Proxy>, timeout: std::time::Duration) -> Result<Box<dyn Transport>, std::io::Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		Ok(match (tls_config, proxy) {
+			(&Some(ref tls_config), &Some(ref proxy)) => {
+				let tunnel = proxy.connect(address, server, true, timeout)?;
+				Box::new(super::tls::TlsStream::handshake(tunnel.0, host, tls_config.clone(), super::time_remaining(timeout_point))?)
+			},
+			(&None, &Some(ref proxy)) => Box::new(proxy.connect(address, server, proxy.protocol() != super::ProxyProtocol::Http, timeout)?),
+			(&Some(ref tls_config), &None) => Box::new(super::tls::TlsStream::connect(address, host, tls_config.clone(), timeout)?),
+			(&None, &None) => Box::new(network_io::TcpStream::connect(address, timeout)?)
+		})
 	}
 }
 
@@ -69,4 +130,4 @@ impl http::WriteableStream for Connection {
 	fn write(&mut self, data: &[u8], data_pos: &mut usize, timeout: std::time::Duration) -> Result<(), std::io::Error> {
 		self.stream.write(data, data_pos, timeout)
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,231 @@
+use std;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::sync::Mutex;
+use super::error::Error;
+
+static INDEX_MAGIC: &str = "http_file-cache v1";
+/// The name of the lock-file that serializes opening and evicting entries
+static DIRECTORY_LOCK: &str = "directory.lock";
+
+/// Computes the 64-bit FNV-1a-hash of `data` (unlike `DefaultHasher` this is stable across
+/// compiler-versions which is necessary for persistent keys)
+fn fnv1a(data: &[u8]) -> u64 {
+	data.iter().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Calls `f` while holding a shared or exclusive lock on `file`
+fn with_lock<T, F: FnOnce() -> Result<T, std::io::Error>>(file: &std::fs::File, shared: bool, f: F) -> Result<T, std::io::Error> {
+	if shared { file.lock_shared()? } else { file.lock()? }
+	let result = f();
+	file.unlock()?;
+	result
+}
+
+/// Opens (or creates) `path` for reading and writing
+fn open_file(path: &std::path::Path) -> Result<std::fs::File, std::io::Error> {
+	std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+}
+
+/// Returns the amount of bytes cached by the entry described by the index-file-contents `index`
+fn cached_bytes(index: &[u8]) -> Option<u64> {
+	// Parse the header (magic, chunk-size, size, validator, URL)
+	let mut header_len = 0;
+	let mut lines = index.split(|b| *b == b'\n');
+	for _ in 0..5 { header_len += lines.next()?.len() + 1 }
+	let chunk_size: u64 = std::str::from_utf8(index.split(|b| *b == b'\n').nth(1)?).ok()?.parse().ok()?;
+	
+	// Count the cached chunks
+	let chunk_count = index.get(header_len..)?.iter().map(|byte| byte.count_ones() as u64).sum::<u64>();
+	Some(chunk_count * chunk_size)
+}
+
+
+
+/// The file-handles of a cache-entry
+struct Files {
+	index: std::fs::File,
+	data: std::fs::File
+}
+
+/// The amount of bytes used by the cache-directory
+struct Usage {
+	bytes: u64,
+	exhausted: bool
+}
+
+/// A persistent chunk-cache-entry for a single resource-version
+///
+/// Each entry consists of a sparse data-file and an index-file that contains a header and a
+/// bitmap of the cached chunks. Concurrent processes are synchronized using advisory file-locks
+/// on the index-file; a chunk is only marked as cached after it has been written completely.
+///
+/// Every open entry holds a shared lock on it's lock-file, so that it is not evicted by another
+/// process while it is in use; opening and evicting entries is serialized by a lock on the
+/// directory-lock-file.
+pub struct DiskCache {
+	directory: std::path::PathBuf,
+	key: String,
+	size: u64,
+	chunk_size: usize,
+	header_len: u64,
+	budget: u64,
+	usage: Mutex<Usage>,
+	files: Mutex<Files>,
+	directory_lock: std::fs::File,
+	_entry_lock: std::fs::File
+}
+impl DiskCache {
+	/// Opens (or creates) the cache-entry for `url` in the version identified by `validator`
+	/// within `directory`; all entries in `directory` together are limited to `budget` bytes
+	///
+	/// _Note: Returns `None` if the entry is invalid but cannot be reset because it is in use by
+	/// another handle_
+	pub fn open<T: AsRef<std::path::Path>>(directory: T, budget: u64, url: &str, validator: &str, size: u64, chunk_size: usize) -> Result<Option<Self>, Error> {
+		let directory = directory.as_ref().to_path_buf();
+		try_err!(std::fs::create_dir_all(&directory), "Failed to create the cache-directory".to_owned());
+		let directory_lock = try_err!(open_file(&directory.join(DIRECTORY_LOCK)), "Failed to open the cache-directory-lock".to_owned());
+		
+		// The key includes the chunk-size because the bitmap depends on it
+		let key = format!("{:016x}", fnv1a(format!("{}\n{}\n{}", url, validator, chunk_size).as_bytes()));
+		let path = |extension: &str| directory.join(format!("{}.{}", key, extension));
+		let header = format!("{}\n{}\n{}\n{}\n{}\n", INDEX_MAGIC, chunk_size, size, validator, url);
+		let bitmap_len = ((size + chunk_size as u64 - 1) / chunk_size as u64 + 7) / 8;
+		
+		// Open, validate and lock the entry (an entry cannot be opened or evicted by others meanwhile)
+		let entry = try_err!(with_lock(&directory_lock, false, || {
+			let (entry_lock, index, data) = (open_file(&path("lock"))?, open_file(&path("index"))?, open_file(&path("data"))?);
+			let mut existing = Vec::new();
+			(&index).read_to_end(&mut existing)?;
+			if existing.len() as u64 != header.len() as u64 + bitmap_len || !existing.starts_with(header.as_bytes()) {
+				// Reset the entry (only if it is not in use)
+				if entry_lock.try_lock().is_err() { return Ok(None) }
+				let mut index_file = &index;
+				index_file.set_len(0)?;
+				index_file.seek(SeekFrom::Start(0))?;
+				index_file.write_all(header.as_bytes())?;
+				index_file.write_all(&vec![0u8; bitmap_len as usize])?;
+				data.set_len(0)?;
+				data.set_len(size)?;
+				entry_lock.unlock()?;
+			}
+			entry_lock.lock_shared()?;
+			Ok(Some((entry_lock, index, data)))
+		}), "Failed to open the cache-entry".to_owned());
+		let (entry_lock, index, data) = match entry {
+			Some(entry) => entry,
+			None => return Ok(None)
+		};
+		
+		let disk_cache = DiskCache {
+			directory, key, size, chunk_size,
+			header_len: header.len() as u64, budget, usage: Mutex::new(Usage{ bytes: 0, exhausted: false }), files: Mutex::new(Files{ index, data }),
+			directory_lock, _entry_lock: entry_lock
+		};
+		if let Ok(bytes) = disk_cache.enforce_budget(0) { disk_cache.usage.lock().unwrap().bytes = bytes }
+		Ok(Some(disk_cache))
+	}
+	
+	/// Loads the chunk at `aligned_offset` into `buffer` and returns whether the chunk was cached or
+	/// not
+	pub fn load(&self, aligned_offset: u64, buffer: &mut[u8]) -> bool {
+		let (chunk, length) = (aligned_offset / self.chunk_size as u64, std::cmp::min((self.size - aligned_offset) as usize, buffer.len()));
+		let files = self.files.lock().unwrap();
+		
+		let result = with_lock(&files.index, true, || {
+			let (mut index, mut data) = (&files.index, &files.data);
+			
+			// Check the bitmap
+			let mut byte = [0u8];
+			index.seek(SeekFrom::Start(self.header_len + chunk / 8))?;
+			index.read_exact(&mut byte)?;
+			if byte[0] & (1 << (chunk % 8)) == 0 { return Ok(false) }
+			
+			// Read the chunk
+			data.seek(SeekFrom::Start(aligned_offset))?;
+			data.read_exact(&mut buffer[.. length])?;
+			Ok(true)
+		});
+		result.unwrap_or(false)
+	}
+	
+	/// Stores `data` as the chunk at `aligned_offset` (errors are ignored because the cache is
+	/// best-effort)
+	///
+	/// _Note: Once the budget is exhausted (i.e. there are no more entries that could be evicted),
+	/// no further chunks are stored until the entry is reopened_
+	pub fn store(&self, aligned_offset: u64, data: &[u8]) {
+		let (chunk, length) = (aligned_offset / self.chunk_size as u64, std::cmp::min((self.size - aligned_offset) as usize, data.len()));
+		
+		// Check the budget (the usage is locked during the eviction so that concurrent stores don't
+		// rescan the directory)
+		let mut usage = self.usage.lock().unwrap();
+		if usage.exhausted { return }
+		if usage.bytes + length as u64 > self.budget {
+			usage.bytes = self.enforce_budget(length as u64).unwrap_or(self.budget);
+			if usage.bytes + length as u64 > self.budget { usage.exhausted = true; return }
+		}
+		
+		let files = self.files.lock().unwrap();
+		let result = with_lock(&files.index, false, || {
+			let (mut index, mut data_file) = (&files.index, &files.data);
+			
+			// Check the bitmap (the chunk might have been stored by another process already)
+			let mut byte = [0u8];
+			index.seek(SeekFrom::Start(self.header_len + chunk / 8))?;
+			index.read_exact(&mut byte)?;
+			if byte[0] & (1 << (chunk % 8)) != 0 { return Ok(false) }
+			
+			// Write the chunk before marking it as cached
+			data_file.seek(SeekFrom::Start(aligned_offset))?;
+			data_file.write_all(&data[.. length])?;
+			
+			// Update the bitmap
+			byte[0] |= 1 << (chunk % 8);
+			index.seek(SeekFrom::Start(self.header_len + chunk / 8))?;
+			index.write_all(&byte)?;
+			Ok(true)
+		});
+		if let Ok(true) = result { usage.bytes += length as u64 }
+	}
+	
+	/// Evicts the least-recently-modified entries that are not in use (by any process) until the
+	/// cache-directory has room for `required` additional bytes and returns the resulting usage
+	fn enforce_budget(&self, required: u64) -> Result<u64, std::io::Error> {
+		with_lock(&self.directory_lock, false, || {
+			// Collect all entries
+			let mut entries = Vec::new();
+			for entry in std::fs::read_dir(&self.directory)? {
+				let path = entry?.path();
+				if path.extension().map(|extension| extension != "index").unwrap_or(true) { continue }
+				
+				let mut index = Vec::new();
+				if std::fs::File::open(&path).and_then(|mut file| file.read_to_end(&mut index)).is_err() { continue }
+				let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).unwrap_or(std::time::UNIX_EPOCH);
+				entries.push((modified, path, cached_bytes(&index).unwrap_or(0)));
+			}
+			
+			// Evict the oldest entries until we fit into the budget
+			entries.sort_by(|a, b| a.0.cmp(&b.0));
+			let mut usage: u64 = entries.iter().map(|entry| entry.2).sum();
+			for (_, path, cached) in entries {
+				if usage + required <= self.budget { break }
+				if path.file_stem().map(|stem| stem == self.key.as_str()).unwrap_or(false) { continue }
+				
+				// Skip the entry if it is in use (every open entry holds a shared lock)
+				let entry_lock = match open_file(&path.with_extension("lock")) {
+					Ok(entry_lock) => entry_lock,
+					Err(_) => continue
+				};
+				if entry_lock.try_lock().is_err() { continue }
+				let _ = std::fs::remove_file(path.with_extension("data"));
+				if std::fs::remove_file(&path).is_ok() { usage -= cached }
+				
+				// The lock-file is removed last (and after closing it because some platforms cannot
+				// remove open files); this is safe because we still hold the directory-lock
+				drop(entry_lock);
+				let _ = std::fs::remove_file(path.with_extension("lock"));
+			}
+			Ok(usage)
+		})
+	}
+}
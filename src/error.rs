@@ -21,16 +21,130 @@ pub enum ErrorType {
 	/// The parameter might be valid but us unsupported
 	Unsupported,
 	
+	/// The remote resource has changed since it was opened
+	ResourceChanged,
+	/// The server requires authentication or rejected the credentials
+	AuthenticationFailed,
+	/// The server responded with an unexpected HTTP-status
+	HttpStatus {
+		/// The status-code
+		code: u16,
+		/// The reason-phrase
+		reason: String,
+		/// The response-header-fields
+		headers: std::collections::HashMap<String, String>
+	},
+	/// The server was still throttling the requests (e.g. `429`/`503`) when the timeout expired
+	Throttled,
+	
 	/// Another error
 	Other(String)
 }
+impl ErrorType {
+	/// Returns the HTTP-status-code if the error is an `HttpStatus`-error
+	pub fn status_code(&self) -> Option<u16> {
+		match *self {
+			ErrorType::HttpStatus{ code, .. } => Some(code),
+			_ => None
+		}
+	}
+	
+	/// Checks if the server reported that the resource does not exist (`404` or `410`)
+	pub fn is_not_found(&self) -> bool {
+		matches!(self.status_code(), Some(404) | Some(410))
+	}
+	
+	/// Checks if the server denied the access (`403`)
+	///
+	/// _Note: A `401` is never reported as `HttpStatus` but as `AuthenticationFailed` (the
+	/// challenge is answered or rejected before)_
+	pub fn is_forbidden(&self) -> bool {
+		matches!(self.status_code(), Some(403))
+	}
+	
+	/// Checks if the server reported a client-error (`4xx`)
+	pub fn is_client_error(&self) -> bool {
+		self.status_code().map(|code| (400 .. 500).contains(&code)).unwrap_or(false)
+	}
+	
+	/// Checks if the server reported a server-error (`5xx`)
+	pub fn is_server_error(&self) -> bool {
+		self.status_code().map(|code| (500 .. 600).contains(&code)).unwrap_or(false)
+	}
+	
+	/// Checks if the error is transient (i.e. retrying the operation later might succeed)
+	pub fn is_retryable(&self) -> bool {
+		match *self {
+			ErrorType::HttpStatus{ code, .. } => matches!(code, 408 | 429 | 500 | 502 | 503 | 504),
+			ErrorType::IOReadWriteError | ErrorType::Throttled => true,
+			ErrorType::GenericIOError(ref error) => matches!(error.kind(),
+				std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::BrokenPipe |
+				std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::UnexpectedEof
+			),
+			_ => false
+		}
+	}
+}
+impl ErrorType {
+	/// Maps an IO-error-kind to the corresponding error-type (`None` means `GenericIOError`)
+	fn from_io_kind(kind: std::io::ErrorKind) -> Option<Self> {
+		match kind {
+			std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => Some(ErrorType::IOAccessError),
+			std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::UnexpectedEof => Some(ErrorType::IOReadWriteError),
+			_ => None
+		}
+	}
+	
+	/// Maps the error-type to the corresponding IO-error-kind
+	fn io_kind(&self) -> std::io::ErrorKind {
+		match *self {
+			ErrorType::GenericIOError(ref error) => error.kind(),
+			ErrorType::InvalidData => std::io::ErrorKind::InvalidData,
+			ErrorType::ResourceError => std::io::ErrorKind::Other,
+			ErrorType::IOAccessError => std::io::ErrorKind::PermissionDenied,
+			ErrorType::IOReadWriteError => std::io::ErrorKind::UnexpectedEof,
+			ErrorType::InvalidParameter => std::io::ErrorKind::InvalidInput,
+			ErrorType::Unsupported => std::io::ErrorKind::Other,
+			ErrorType::ResourceChanged => std::io::ErrorKind::InvalidData,
+			ErrorType::AuthenticationFailed => std::io::ErrorKind::PermissionDenied,
+			ErrorType::HttpStatus{ code: 404, .. } | ErrorType::HttpStatus{ code: 410, .. } => std::io::ErrorKind::NotFound,
+			ErrorType::HttpStatus{ code: 401, .. } | ErrorType::HttpStatus{ code: 403, .. } => std::io::ErrorKind::PermissionDenied,
+			ErrorType::HttpStatus{ code: 408, .. } | ErrorType::HttpStatus{ code: 504, .. } => std::io::ErrorKind::TimedOut,
+			ErrorType::HttpStatus{ .. } => std::io::ErrorKind::Other,
+			ErrorType::Throttled => std::io::ErrorKind::TimedOut,
+			ErrorType::Other(_) => std::io::ErrorKind::Other
+		}
+	}
+}
+impl std::fmt::Display for ErrorType {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			ErrorType::InvalidData => write!(f, "Invalid data"),
+			ErrorType::ResourceError => write!(f, "Not enough resources"),
+			ErrorType::IOAccessError => write!(f, "Failed to access the resource"),
+			ErrorType::IOReadWriteError => write!(f, "Failed to read from or write to the connection"),
+			ErrorType::GenericIOError(ref error) => write!(f, "IO-error ({})", error.kind()),
+			ErrorType::InvalidParameter => write!(f, "Invalid parameter"),
+			ErrorType::Unsupported => write!(f, "Unsupported operation"),
+			ErrorType::ResourceChanged => write!(f, "The remote resource has changed"),
+			ErrorType::AuthenticationFailed => write!(f, "Authentication failed"),
+			ErrorType::HttpStatus{ code, ref reason, .. } => write!(f, "HTTP-error {}: {}", code, reason),
+			ErrorType::Throttled => write!(f, "The server is throttling the requests"),
+			ErrorType::Other(ref description) => write!(f, "{}", description)
+		}
+	}
+}
+impl std::error::Error for ErrorType {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match *self {
+			ErrorType::GenericIOError(ref error) => Some(error),
+			_ => None
+		}
+	}
+}
 impl From<std::io::Error> for ErrorType {
 	fn from(error: std::io::Error) -> Self {
-		match error.kind() {
-			std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => ErrorType::IOAccessError,
-			std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::UnexpectedEof => ErrorType::IOReadWriteError,
-			_ => ErrorType::GenericIOError(error)
-		}
+		ErrorType::from_io_kind(error.kind()).unwrap_or(ErrorType::GenericIOError(error))
 	}
 }
 impl From<std::str::Utf8Error> for ErrorType {
@@ -48,6 +162,9 @@ impl From<std::num::ParseIntError> for ErrorType {
 
 #[derive(Debug)]
 /// An error-describing structure containing the error and it's file/line
+///
+/// _Note: The underlying error (e.g. an IO- or a TLS-error) is available through
+/// `std::error::Error::source`_
 pub struct Error {
 	/// The error-type
 	pub error_type: ErrorType,
@@ -56,7 +173,63 @@ pub struct Error {
 	/// The file in which the error occurred
 	pub file: &'static str,
 	/// The line on which the error occurred
-	pub line: u32
+	pub line: u32,
+	/// The underlying error (if it is not contained in `error_type`)
+	///
+	/// _Note: This field is public because the exported `new_err!`-macro constructs the error
+	/// in-place; use `std::error::Error::source` to access the underlying error_
+	pub cause: Option<Box<dyn std::error::Error + Send + Sync>>
+}
+impl Error {
+	/// Creates an error caused by `cause` at the caller's location
+	#[track_caller]
+	fn caused_by(error_type: ErrorType, cause: Option<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+		let location = std::panic::Location::caller();
+		Error{ error_type, description: String::new(), file: location.file(), line: location.line(), cause }
+	}
+}
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		if self.description.is_empty() { write!(f, "{}", self.error_type) }
+			else { write!(f, "{}", self.description) }
+	}
+}
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self.cause {
+			Some(ref cause) => Some(&**cause),
+			None => self.error_type.source()
+		}
+	}
+}
+impl From<std::io::Error> for Error {
+	#[track_caller]
+	fn from(error: std::io::Error) -> Self {
+		match ErrorType::from_io_kind(error.kind()) {
+			Some(error_type) => Error::caused_by(error_type, Some(Box::new(error))),
+			None => Error::caused_by(ErrorType::GenericIOError(error), None)
+		}
+	}
+}
+impl From<std::str::Utf8Error> for Error {
+	#[track_caller]
+	fn from(error: std::str::Utf8Error) -> Self {
+		Error::caused_by(ErrorType::InvalidData, Some(Box::new(error)))
+	}
+}
+impl From<std::num::ParseIntError> for Error {
+	#[track_caller]
+	fn from(error: std::num::ParseIntError) -> Self {
+		Error::caused_by(ErrorType::InvalidData, Some(Box::new(error)))
+	}
+}
+
+impl From<Error> for std::io::Error {
+	/// Wraps the error into an IO-error with the corresponding kind (the error including the source and
+	/// the file/line remains accessible via `std::io::Error::get_ref`)
+	fn from(error: Error) -> Self {
+		std::io::Error::new(error.error_type.io_kind(), error)
+	}
 }
 
 #[macro_export]
@@ -66,16 +239,17 @@ macro_rules! new_err {
 		error_type: $error_type,
 		description: $description,
 		file: file!(),
-		line: line!()
+		line: line!(),
+		cause: None
 	}));
-	($error_type:expr) => (new_err!($error_type, "".to_owned()));
+	($error_type:expr) => ($crate::new_err!($error_type, "".to_owned()));
 }
 
 #[macro_export]
 /// Create an error from an `ErrorType`
 macro_rules! throw_err {
-	($error_type:expr, $description:expr) => (return new_err!($error_type, $description));
-	($error_type:expr) => (throw_err!($error_type, "".to_owned()));
+	($error_type:expr, $description:expr) => (return $crate::new_err!($error_type, $description));
+	($error_type:expr) => ($crate::throw_err!($error_type, "".to_owned()));
 }
 
 #[macro_export]
@@ -83,7 +257,124 @@ macro_rules! throw_err {
 macro_rules! try_err {
 	($code:expr, $description:expr) => (match $code {
 		Ok(result) => result,
-		Err(error) => throw_err!($crate::error::ErrorType::from(error), $description)
+		Err(error) => {
+			// Keep the error as source
+			let mut error = $crate::error::Error::from(error);
+			error.description = $description;
+			return Err(error)
+		}
 	});
-	($code:expr) => (try_err!($code, "".to_owned()))
-}
\ No newline at end of file
+	($code:expr) => ($crate::try_err!($code, "".to_owned()))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std;
+	use std::error::Error as StdError;
+	use super::{Error, ErrorType};
+	
+	/// Creates an `HttpStatus`-error-type
+	fn http_status(code: u16) -> ErrorType {
+		ErrorType::HttpStatus{ code, reason: "Reason".to_owned(), headers: std::collections::HashMap::new() }
+	}
+	
+	/// Returns the IO-error-kind an error with `error_type` is converted to
+	fn io_kind(error_type: ErrorType) -> std::io::ErrorKind {
+		let error: Result<(), Error> = new_err!(error_type);
+		std::io::Error::from(error.unwrap_err()).kind()
+	}
+	
+	#[test]
+	fn from_io_error() {
+		let kinds = [
+			(std::io::ErrorKind::NotFound, "IOAccessError"), (std::io::ErrorKind::PermissionDenied, "IOAccessError"),
+			(std::io::ErrorKind::ConnectionReset, "IOReadWriteError"), (std::io::ErrorKind::ConnectionAborted, "IOReadWriteError"),
+			(std::io::ErrorKind::BrokenPipe, "IOReadWriteError"), (std::io::ErrorKind::UnexpectedEof, "IOReadWriteError")
+		];
+		for &(kind, error_type) in kinds.iter() {
+			let (error, line) = (Error::from(std::io::Error::new(kind, "Cause")), line!());
+			assert_eq!(format!("{:?}", error.error_type), error_type);
+			assert_eq!((error.file, error.line), (file!(), line));
+			
+			// The IO-error is kept as cause
+			let source = error.source().unwrap().downcast_ref::<std::io::Error>().unwrap();
+			assert_eq!((source.kind(), source.to_string()), (kind, "Cause".to_owned()));
+		}
+	}
+	
+	#[test]
+	fn from_io_error_generic() {
+		for &kind in [std::io::ErrorKind::TimedOut, std::io::ErrorKind::WouldBlock, std::io::ErrorKind::Other].iter() {
+			let error = Error::from(std::io::Error::new(kind, "Cause"));
+			match error.error_type {
+				ErrorType::GenericIOError(ref io_error) => assert_eq!(io_error.kind(), kind),
+				ref error_type => panic!("Unexpected error-type {:?}", error_type)
+			}
+			assert!(error.cause.is_none());
+			
+			// The IO-error is the source of both the error and the error-type
+			let source = error.source().unwrap().downcast_ref::<std::io::Error>().unwrap();
+			assert_eq!((source.kind(), source.to_string()), (kind, "Cause".to_owned()));
+			assert!(error.error_type.source().unwrap().is::<std::io::Error>());
+		}
+	}
+	
+	#[test]
+	fn try_err() {
+		fn parse(value: &str) -> Result<u16, Error> {
+			Ok(try_err!(value.parse::<u16>(), "Invalid number".to_owned()))
+		}
+		
+		let error = parse("x").unwrap_err();
+		assert!(matches!(error.error_type, ErrorType::InvalidData));
+		assert_eq!(error.to_string(), "Invalid number");
+		assert!(error.source().unwrap().is::<std::num::ParseIntError>());
+		assert_eq!(parse("7").unwrap(), 7);
+	}
+	
+	#[test]
+	fn to_io_error() {
+		assert_eq!(io_kind(ErrorType::InvalidData), std::io::ErrorKind::InvalidData);
+		assert_eq!(io_kind(ErrorType::IOAccessError), std::io::ErrorKind::PermissionDenied);
+		assert_eq!(io_kind(ErrorType::IOReadWriteError), std::io::ErrorKind::UnexpectedEof);
+		assert_eq!(io_kind(ErrorType::InvalidParameter), std::io::ErrorKind::InvalidInput);
+		assert_eq!(io_kind(ErrorType::ResourceChanged), std::io::ErrorKind::InvalidData);
+		assert_eq!(io_kind(ErrorType::AuthenticationFailed), std::io::ErrorKind::PermissionDenied);
+		assert_eq!(io_kind(ErrorType::Throttled), std::io::ErrorKind::TimedOut);
+		assert_eq!(io_kind(ErrorType::Other("Other".to_owned())), std::io::ErrorKind::Other);
+		assert_eq!(io_kind(ErrorType::GenericIOError(std::io::Error::from(std::io::ErrorKind::AddrInUse))), std::io::ErrorKind::AddrInUse);
+		
+		// HTTP-statuses
+		assert_eq!(io_kind(http_status(401)), std::io::ErrorKind::PermissionDenied);
+		assert_eq!(io_kind(http_status(403)), std::io::ErrorKind::PermissionDenied);
+		assert_eq!(io_kind(http_status(404)), std::io::ErrorKind::NotFound);
+		assert_eq!(io_kind(http_status(410)), std::io::ErrorKind::NotFound);
+		assert_eq!(io_kind(http_status(408)), std::io::ErrorKind::TimedOut);
+		assert_eq!(io_kind(http_status(504)), std::io::ErrorKind::TimedOut);
+		assert_eq!(io_kind(http_status(400)), std::io::ErrorKind::Other);
+		assert_eq!(io_kind(http_status(500)), std::io::ErrorKind::Other);
+	}
+	
+	#[test]
+	fn to_io_error_keeps_error() {
+		let error = Error::from(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Cause"));
+		let io_error = std::io::Error::from(error);
+		assert_eq!(io_error.kind(), std::io::ErrorKind::UnexpectedEof);
+		
+		// The error and its source remain accessible
+		let error = io_error.get_ref().unwrap().downcast_ref::<Error>().unwrap();
+		assert!(matches!(error.error_type, ErrorType::IOReadWriteError));
+		assert_eq!(error.source().unwrap().to_string(), "Cause");
+	}
+	
+	#[test]
+	fn status() {
+		assert!(http_status(404).is_not_found() && http_status(410).is_not_found() && !http_status(403).is_not_found());
+		assert!(http_status(403).is_forbidden() && !http_status(401).is_forbidden());
+		assert!(http_status(499).is_client_error() && !http_status(500).is_client_error());
+		assert!(http_status(503).is_server_error() && !ErrorType::Throttled.is_server_error());
+		assert!(http_status(503).is_retryable() && !http_status(501).is_retryable());
+		assert_eq!(http_status(404).to_string(), "HTTP-error 404: Reason");
+	}
+}
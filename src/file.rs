@@ -1,63 +1,213 @@
 use std;
 use super::error::{Error, ErrorType};
 
-static DEFAULT_CACHE_CHUNK_SIZE: usize = 131_072;
-static DEFAULT_CACHE_CHUNK_COUNT: usize = 2048;
+/// The maximum amount of ranges per multi-range-request
+static MAX_RANGES_PER_REQUEST: usize = 64;
 
 pub struct File {
-	size: u64,
+	source: super::ChunkSource,
 	position: u64,
 	
+	url: String,
+	name: String,
 	uri: super::URI,
-	connection: super::Connection,
 	
 	chunk_buf: Vec<u8>,
-	cache: super::CacheDB
+	chunk_buf_offset: Option<u64>,
+	prefetcher: super::Prefetcher,
+	
+	io_timeout: std::time::Duration
 }
 impl File {
 	/// Opens a URI
 	///
-	/// _Note: If a recoverable error happens this function will retry the operation until the
-	/// `timeout` exceeded. Recoverable errors are:_
-	///
-	///  - `ConnectionReset`
-	///  - `ConnectionAborted`
-	///  - `BrokenPipe`
-	///  - `WouldBlock`
-	///  - `TimedOut` (this applies only to internal timeouts)
+	/// _Note: Recoverable errors are retried according to the default `RetryPolicy` until the
+	/// `timeout` exceeded (see `OpenOptions::retry_policy` to customize this)_
 	pub fn open(uri: &str, timeout: std::time::Duration) -> Result<Self, Error> {
-		let timeout_point = std::time::Instant::now() + timeout;
+		super::OpenOptions::new().timeout(timeout).open(uri)
+	}
+	
+	/// Opens a URI using `tls_config` for `https`-connections
+	///
+	/// _Note: See `open` for the retry-behaviour_
+	pub fn open_with_tls(uri: &str, tls_config: super::TlsConfig, timeout: std::time::Duration) -> Result<Self, Error> {
+		super::OpenOptions::new().timeout(timeout).tls_config(tls_config).open(uri)
+	}
+	
+	/// Opens a URI using `options` (see also `OpenOptions::open`)
+	///
+	/// _Note: See `open` for the retry-behaviour_
+	pub fn open_with_options(uri: &str, options: &super::OpenOptions) -> Result<Self, Error> {
+		let timeout_point = std::time::Instant::now() + options.timeout;
+		let (mut uri, options) = File::prepare(uri, options)?;
+		let (url, name) = (uri.url.clone(), uri.name.clone());
+		let pool = std::sync::Arc::new(super::ConnectionPool::new(options.max_connections));
 		
-		// Parse URI and connect to server
-		let uri = super::URI::parse(uri)?;
-		let mut connection = super::Connection::connect(&uri, super::time_remaining(timeout_point))?;
+		// Receive file-size and follow redirects
+		let info = pool.with_connection(&mut uri, &options, super::time_remaining(timeout_point), |uri, connection| {
+			super::http_action::fetch_resource_info(uri, connection, &options, super::time_remaining(timeout_point))
+		})?;
+		File::from_resource(url, name, uri, options, pool, info)
+	}
+	
+	/// Validates `options`, parses `uri` and returns the URI and the options for a new `File` (the
+	/// credentials are taken from the URI if necessary and a new authentication-state and TLS-client-
+	/// configuration are created)
+	pub(crate) fn prepare(uri: &str, options: &super::OpenOptions) -> Result<(super::URI, super::OpenOptions), Error> {
+		if options.chunk_size == 0 { throw_err!(ErrorType::InvalidParameter, "The chunk-size must not be 0".to_owned()) }
+		for &(ref name, ref value) in options.headers.iter() { super::options::check_header_field(name, value)? }
 		
-		// Receive file-size
-		let size = super::http_action::receive_size(&uri, &mut connection, super::time_remaining(timeout_point))?;
+		let mut uri = super::URI::parse(uri)?;
+		let mut options = options.clone();
+		if options.credentials.is_none() {
+			if let Some((username, password)) = uri.userinfo.take() { options.credentials = Some(super::Credentials::Basic{ username, password }) }
+		}
+		options.auth_state = std::sync::Arc::new(super::auth::AuthState::default());
+		options.auth_state.set_origin(uri.endpoint());
+		options.tls_client_config = std::sync::Arc::new(super::tls::ClientConfigCache::default());
+		Ok((uri, options))
+	}
+	
+	/// Creates a `File` for the resource described by `info` which was received from `uri` (the
+	/// effective URI of the original `url`)
+	pub(crate) fn from_resource(url: String, name: String, uri: super::URI, options: super::OpenOptions, pool: std::sync::Arc<super::ConnectionPool>, info: super::ResourceInfo) -> Result<Self, Error> {
+		let disk_cache = File::open_disk_cache(&options, &url, &info, options.chunk_size)?;
+		let mut cache = super::CacheDB::new(options.chunk_count, options.chunk_size);
+		cache.set_policy(options.cache_policy);
 		
+		let (chunk_size, prefetch_window, io_timeout) = (options.chunk_size, options.prefetch_window, options.io_timeout.unwrap_or(options.timeout));
+		let source = super::ChunkSource {
+			resource: info, options, pool, chunk_size,
+			cache: std::sync::Arc::new(std::sync::Mutex::new(cache)), disk_cache
+		};
 		Ok(File {
-			size, position: 0,
-			uri, connection,
-			chunk_buf: vec![0u8; DEFAULT_CACHE_CHUNK_SIZE], cache: super::CacheDB::new(DEFAULT_CACHE_CHUNK_COUNT, DEFAULT_CACHE_CHUNK_SIZE)
+			source, position: 0,
+			url, name, uri,
+			chunk_buf: vec![0u8; chunk_size], chunk_buf_offset: None, prefetcher: super::Prefetcher::new(prefetch_window),
+			io_timeout
 		})
 	}
 	
 	/// Adjusts the cache-parameters
 	///
-	/// _Note: Changing these parameters will discard all cached chunks_
-	pub fn adjust_cache_size(&mut self, chunk_count: usize, chunk_size: usize) {
+	/// _Note: Changing the chunk-size will discard all cached chunks (including the chunks in the
+	/// disk-cache); shrinking the chunk-count releases the least-recently-used chunks. A chunk-size
+	/// of `0` is rejected and leaves the cache unchanged._
+	pub fn adjust_cache_size(&mut self, chunk_count: usize, chunk_size: usize) -> Result<(), Error> {
+		if chunk_size == 0 { throw_err!(ErrorType::InvalidParameter, "The chunk-size must not be 0".to_owned()) }
+		
+		// Keep the cached chunks if the geometry is compatible
+		if chunk_size == self.source.chunk_size {
+			self.source.cache.lock().unwrap().set_chunk_count(chunk_count);
+			return Ok(())
+		}
+		
+		self.prefetcher.stop();
+		self.source.disk_cache = File::open_disk_cache(&self.source.options, &self.url, &self.source.resource, chunk_size).unwrap_or(None);
 		self.chunk_buf = vec![0u8; chunk_size];
-		self.cache = super::CacheDB::new(chunk_count, chunk_size)
+		self.chunk_buf_offset = None;
+		
+		let mut cache = super::CacheDB::new(chunk_count, chunk_size);
+		cache.set_policy(self.cache_policy());
+		self.source.cache = std::sync::Arc::new(std::sync::Mutex::new(cache));
+		self.source.chunk_size = chunk_size;
+		Ok(())
+	}
+	
+	/// Sets the policy for adaptive cache-resizing (`None` disables adaptive resizing)
+	///
+	/// _Note: Resizing never discards cached chunks unless the cache shrinks_
+	pub fn set_cache_policy(&mut self, cache_policy: Option<super::CachePolicy>) {
+		self.source.cache.lock().unwrap().set_policy(cache_policy)
+	}
+	
+	/// Returns the policy for adaptive cache-resizing
+	pub fn cache_policy(&self) -> Option<super::CachePolicy> {
+		self.source.cache.lock().unwrap().policy()
+	}
+	
+	/// Returns the current maximum chunk-count of the in-memory-cache
+	pub fn cache_chunk_count(&self) -> usize {
+		self.source.cache.lock().unwrap().chunk_count()
+	}
+	
+	/// Returns the amount of memory currently allocated by the in-memory-cache
+	///
+	/// _Note: The cache allocates it's chunks on demand up to the configured chunk-count_
+	pub fn cache_resident_size(&self) -> usize {
+		self.source.cache.lock().unwrap().resident_size()
+	}
+	
+	/// Sets the amount of chunks that are fetched ahead in the background if a sequential access-
+	/// pattern is detected (`0` disables prefetching)
+	pub fn set_prefetch_window(&mut self, prefetch_window: usize) {
+		self.prefetcher.set_window(prefetch_window)
+	}
+	
+	/// Returns the prefetch-window
+	pub fn prefetch_window(&self) -> usize {
+		self.prefetcher.window()
+	}
+	
+	/// Returns the maximum amount of concurrent connections per host
+	pub fn max_connections(&self) -> usize {
+		self.source.pool.max_connections()
+	}
+	
+	/// Sets the timeout used by the `std::io::Read`-, `std::io::Seek`- and `std::io::BufRead`-
+	/// implementations
+	///
+	/// _Note: The timeout defaults to the timeout passed to `open`_
+	pub fn set_io_timeout(&mut self, timeout: std::time::Duration) {
+		self.io_timeout = timeout
+	}
+	
+	/// Returns the timeout used by the `std::io`-trait-implementations
+	pub fn io_timeout(&self) -> std::time::Duration {
+		self.io_timeout
+	}
+	
+	/// Sets a header-field that is sent with all subsequent requests (replaces an existing field
+	/// with the same name)
+	///
+	/// _Note: See `OpenOptions::header` for the protected fields and the fields that are sent to
+	/// other servers; the prefetch-worker is restarted so that it uses the new field, too_
+	pub fn set_header(&mut self, name: &str, value: &str) -> Result<(), Error> {
+		super::options::check_header_field(name, value)?;
+		super::options::set_header_field(&mut self.source.options.headers, name, Some(value));
+		self.prefetcher.stop();
+		Ok(())
+	}
+	
+	/// Removes a header-field (including the default `User-Agent`)
+	///
+	/// _Note: The prefetch-worker is restarted so that it does not send the field anymore_
+	pub fn remove_header(&mut self, name: &str) {
+		super::options::set_header_field(&mut self.source.options.headers, name, None);
+		self.prefetcher.stop()
+	}
+	
+	/// Returns the custom header-fields that are sent with every request
+	pub fn headers(&self) -> &[(String, String)] {
+		&self.source.options.headers
 	}
 	
 	/// Returns the file-size
 	pub fn size(&self) -> u64 {
-		self.size
+		self.source.resource.size
 	}
 	
 	/// Returns the file-name
+	///
+	/// _Note: The file-name is always taken from the URI passed to `open` and not from the
+	/// effective URL after redirects_
 	pub fn name(&self) -> &str {
-		&self.uri.name
+		&self.name
+	}
+	
+	/// Returns the effective URL (i.e. the URL after all redirects have been followed)
+	pub fn effective_url(&self) -> &str {
+		&self.uri.url
 	}
 	
 	/// Returns the current file-position
@@ -78,7 +228,7 @@ impl File {
 		// Check if we need to increment or decrement the counter
 		if by > 0 {
 			// Validate boundaries
-			if position + by_u64 > self.size { throw_err!(ErrorType::InvalidParameter, format!("Cannot seek beyond EOF ({})", self.size)) }
+			if position + by_u64 > self.source.resource.size { throw_err!(ErrorType::InvalidParameter, format!("Cannot seek beyond EOF ({})", self.source.resource.size)) }
 			self.position = position + by_u64;
 		} else if by < 0 {
 			// Validate position
@@ -110,61 +260,524 @@ impl File {
 	/// _Note: if the amount of bytes read is smaller than `buffer.len()` this ALWAYS means that the
 	/// `EOF` was reached. Otherwise an error would be returned._
 	pub fn read_at(&mut self, buffer: &mut[u8], offset: u64, timeout: std::time::Duration) -> Result<usize, Error> {
+		// Check if there is anything to read
+		if offset >= self.source.resource.size || buffer.len() == 0 { return Ok(0) }
+		
 		// Compute the amount of bytes to read
-		let to_read = std::cmp::min(buffer.len(), (self.size - offset) as usize);
+		let to_read = std::cmp::min(buffer.len(), (self.source.resource.size - offset) as usize);
 		
 		// Read bytes
-		self.read_range(&mut buffer[..to_read], offset, timeout)?;
+		self.with_reopen(timeout, |file| file.read_range(&mut buffer[..to_read], offset, timeout))?;
 		Ok(to_read)
 	}
 	
+	/// Reads multiple `(offset, buffer)`-pairs and returns the amount of bytes read for each pair
+	///
+	/// All chunks needed by the pairs are fetched once (using multi-range-requests if possible or
+	/// concurrently otherwise) before the data is scattered into the buffers, which makes reading
+	/// scattered small regions (e.g. ZIP-central-directories or Parquet-footers) much faster than
+	/// calling `read_at` for each pair.
+	///
+	/// _Note: If the pairs span more chunks than the cache can hold, they are processed in batches_
+	pub fn read_vectored_at(&mut self, requests: &mut[(u64, &mut[u8])], timeout: std::time::Duration) -> Result<Vec<usize>, Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		let (size, chunk_size) = (self.source.resource.size, self.source.chunk_size as u64);
+		let mut read = vec![0; requests.len()];
+		
+		let mut start = 0;
+		while start < requests.len() {
+			// Collect the largest batch whose chunks fit into the cache
+			let capacity = std::cmp::max(self.cache_chunk_count(), 1) as u64;
+			let (mut end, mut chunks) = (start, 0);
+			while end < requests.len() {
+				let (offset, length) = (requests[end].0, requests[end].1.len() as u64);
+				let count = if offset >= size || length == 0 { 0 }
+					else { (std::cmp::min(offset.saturating_add(length), size) - 1) / chunk_size - offset / chunk_size + 1 };
+				if end > start && chunks + count > capacity { break }
+				chunks += count;
+				end += 1;
+			}
+			
+			// Fetch the chunks of the batch and scatter the data
+			let ranges: Vec<(u64, usize)> = requests[start..end].iter().map(|&(offset, ref buffer)| (offset, buffer.len())).collect();
+			self.fetch_ranges(&ranges, super::time_remaining(timeout_point))?;
+			for (&mut (offset, ref mut buffer), read) in requests[start..end].iter_mut().zip(read[start..end].iter_mut()) {
+				*read = self.read_at(buffer, offset, super::time_remaining(timeout_point))?;
+			}
+			start = end;
+		}
+		Ok(read)
+	}
+	
+	/// Fetches all chunks that overlap with `ranges` (offset and length) into the cache using as few
+	/// multi-range-requests as possible, so that subsequent reads of these ranges are served from the
+	/// cache
+	///
+	/// _Note: If the server refuses multi-range-requests, the chunks are fetched separately; if the
+	/// ranges span more chunks than the cache can hold, only the first chunks are fetched_
+	pub fn fetch_ranges(&mut self, ranges: &[(u64, usize)], timeout: std::time::Duration) -> Result<(), Error> {
+		self.with_reopen(timeout, |file| file.fetch_multi_range(ranges, timeout))
+	}
+	
+	/// Converts the `File` into a thread-safe `SharedFile` that keeps the connection-pool and the
+	/// caches
+	///
+	/// _Note: Background-prefetching is stopped because a `SharedFile` has no access-pattern_
+	pub fn into_shared(self) -> super::SharedFile {
+		let File{ source, name, uri, mut prefetcher, .. } = self;
+		prefetcher.stop();
+		
+		super::SharedFile::from_inner(super::shared::Inner {
+			source, name, uri: std::sync::Mutex::new(uri),
+			in_flight: std::sync::Mutex::new(std::collections::HashSet::new()),
+			fetched: std::sync::Condvar::new()
+		})
+	}
 	
+	/// Returns the `ETag` of the resource (if any)
+	pub fn etag(&self) -> Option<&str> {
+		self.source.resource.etag.as_ref().map(|etag| etag.as_str())
+	}
+	
+	/// Returns the `Last-Modified`-date of the resource (if any)
+	pub fn last_modified(&self) -> Option<&str> {
+		self.source.resource.last_modified.as_ref().map(|last_modified| last_modified.as_str())
+	}
+	
+	
+	
+	/// Calls `f` and reopens the resource if `f` fails with `ErrorType::ResourceChanged` and
+	/// `reopen_on_change` is enabled; `f` is retried if the resource-size did not change
+	fn with_reopen<T, F: FnMut(&mut Self) -> Result<T, Error>>(&mut self, timeout: std::time::Duration, mut f: F) -> Result<T, Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		
+		match f(self) {
+			Err(Error{ error_type: ErrorType::ResourceChanged, .. }) if self.source.options.reopen_on_change => {
+				let size = self.source.resource.size;
+				self.reopen(super::time_remaining(timeout_point))?;
+				if self.source.resource.size != size { throw_err!(ErrorType::ResourceChanged, format!("The resource-size has changed from {} to {}", size, self.source.resource.size)) }
+				f(self)
+			},
+			result => result
+		}
+	}
+	
+	/// Receives the resource-information again and discards all cached chunks
+	fn reopen(&mut self, timeout: std::time::Duration) -> Result<(), Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		self.prefetcher.stop();
+		
+		// Receive the new resource-information and follow redirects
+		let options = &self.source.options;
+		let resource = self.source.pool.with_connection(&mut self.uri, &options, timeout, |uri, connection| {
+			super::http_action::fetch_resource_info(uri, connection, options, super::time_remaining(timeout_point))
+		})?;
+		
+		// Discard cached chunks
+		self.source.cache.lock().unwrap().clear();
+		self.chunk_buf_offset = None;
+		self.source.disk_cache = File::open_disk_cache(&self.source.options, &self.url, &resource, self.source.chunk_size)?;
+		self.source.resource = resource;
+		Ok(())
+	}
+	
+	/// Opens the disk-cache-entry for the resource if the disk-cache is enabled and the server sent a
+	/// validator
+	///
+	/// _Note: The original URL is used as key because the effective URL might be volatile (e.g. a
+	/// signed URL)_
+	fn open_disk_cache(options: &super::OpenOptions, url: &str, resource: &super::ResourceInfo, chunk_size: usize) -> Result<Option<std::sync::Arc<super::DiskCache>>, Error> {
+		match (&options.disk_cache, resource.validator()) {
+			(&Some((ref directory, budget)), Some(validator)) => {
+				Ok(super::DiskCache::open(directory, budget, url, validator, resource.size, chunk_size)?.map(std::sync::Arc::new))
+			},
+			_ => Ok(None)
+		}
+	}
 	
 	fn read_range(&mut self, buffer: &mut[u8], offset: u64, timeout: std::time::Duration) -> Result<(), Error> {
 		// Compute the aligned boundaries
-		let aligned_offset = (offset / self.chunk_buf.len() as u64) * self.chunk_buf.len() as u64;
+		let chunk_size = self.source.chunk_size;
+		let aligned_offset = (offset / chunk_size as u64) * chunk_size as u64;
 		let skip_left = (offset - aligned_offset) as usize;
-		let chunk_count = ((skip_left + buffer.len()) / self.chunk_buf.len()) + 1;
 		
-		// Read data
-		let (mut buffer_pos, chunk_size) = (0, self.chunk_buf.len());
+		// Read single chunks directly
+		if skip_left + buffer.len() <= chunk_size { return self.read_chunk_into(aligned_offset, skip_left, buffer, timeout) }
 		
-		// Copy first partial block
-		{
-			// Fetch chunk
-			self.read_chunk(aligned_offset, timeout)?;
-			
-			// Copy chunk
-			let to_copy = std::cmp::min(self.chunk_buf.len() - skip_left, buffer.len() - buffer_pos);
-			&mut buffer[buffer_pos..buffer_pos + to_copy].copy_from_slice(&self.chunk_buf[skip_left..skip_left + to_copy]);
-			buffer_pos += to_copy
+		// Split `buffer` into the parts that belong to the individual chunks
+		let mut targets = Vec::new();
+		let (mut rest, mut chunk_offset, mut skip) = (buffer, aligned_offset, skip_left);
+		while !rest.is_empty() {
+			let to_copy = std::cmp::min(chunk_size - skip, rest.len());
+			let (part, tail) = rest.split_at_mut(to_copy);
+			targets.push((chunk_offset, skip, part));
+			rest = tail;
+			chunk_offset += chunk_size as u64;
+			skip = 0;
 		}
 		
-		// Copy remaining chunks
-		for i in 1..chunk_count {
-			// Fetch chunk
-			self.read_chunk(aligned_offset + (i * chunk_size) as u64, timeout)?;
-			
-			// Copy chunk
-			let to_copy = std::cmp::min(self.chunk_buf.len(), buffer.len() - buffer_pos);
-			&mut buffer[buffer_pos..buffer_pos + to_copy].copy_from_slice(&self.chunk_buf[.. to_copy]);
-			buffer_pos += to_copy
-		}
+		// Fetch the chunks concurrently and record the accesses
+		let offsets: Vec<u64> = targets.iter().map(|target| target.0).collect();
+		self.fetch_chunks(targets, timeout)?;
+		for aligned_offset in offsets { self.schedule_prefetch(aligned_offset, timeout) }
 		Ok(())
 	}
 	
+	/// Copies the part of the chunk at `aligned_offset` that starts at `skip` into `buffer`
+	///
+	/// _Note: Cache-hits are copied straight from the cache-entry and chunks that are entirely
+	/// covered by `buffer` are received directly into `buffer`; only partially read chunks are
+	/// received into `chunk_buf`_
+	fn read_chunk_into(&mut self, aligned_offset: u64, skip: usize, buffer: &mut[u8], timeout: std::time::Duration) -> Result<(), Error> {
+		self.schedule_prefetch(aligned_offset, timeout);
+		if self.source.read_cached(aligned_offset, skip, buffer) { return Ok(()) }
+		
+		// Load the chunk directly into `buffer` if it covers the entire chunk
+		let length = self.source.chunk_length(aligned_offset);
+		if skip == 0 && buffer.len() == length { return self.source.load(&mut self.uri, aligned_offset, buffer, timeout) }
+		
+		self.load_chunk_buf(aligned_offset, timeout)?;
+		buffer.copy_from_slice(&self.chunk_buf[skip .. skip + buffer.len()]);
+		Ok(())
+	}
+	
+	/// Reads the chunk at `aligned_offset` into `chunk_buf`
 	fn read_chunk(&mut self, aligned_offset: u64, timeout: std::time::Duration) -> Result<(), Error> {
-		// Compute chunk-size (necessary because the last chunk might be smaller than the usual chunk-size)
-		let chunk_size = std::cmp::min((self.size - aligned_offset) as usize, self.chunk_buf.len());
+		self.schedule_prefetch(aligned_offset, timeout);
+		self.load_chunk_buf(aligned_offset, timeout)
+	}
+	
+	/// Loads the chunk at `aligned_offset` into `chunk_buf` unless `chunk_buf` contains it already
+	fn load_chunk_buf(&mut self, aligned_offset: u64, timeout: std::time::Duration) -> Result<(), Error> {
+		if self.chunk_buf_offset == Some(aligned_offset) { return Ok(()) }
+		
+		// Invalidate `chunk_buf` until the chunk is loaded completely
+		self.chunk_buf_offset = None;
+		let length = self.source.chunk_length(aligned_offset);
+		if !self.source.read_cached(aligned_offset, 0, &mut self.chunk_buf[.. length]) {
+			self.source.load(&mut self.uri, aligned_offset, &mut self.chunk_buf[.. length], timeout)?
+		}
+		self.chunk_buf_offset = Some(aligned_offset);
+		Ok(())
+	}
+	
+	/// Schedules read-ahead if the access-pattern is sequential
+	fn schedule_prefetch(&mut self, aligned_offset: u64, timeout: std::time::Duration) {
+		let cache_chunk_count = self.cache_chunk_count();
+		let prefetch = self.prefetcher.record_access(aligned_offset, self.source.chunk_size, self.source.resource.size, cache_chunk_count);
+		if !prefetch.is_empty() {
+			let (uri, source) = (&self.uri, &self.source);
+			let context = || super::prefetch::Context{ uri: uri.clone(), source: source.clone(), timeout };
+			self.prefetcher.schedule(prefetch, context);
+		}
+	}
+	
+	fn fetch_multi_range(&mut self, ranges: &[(u64, usize)], timeout: std::time::Duration) -> Result<(), Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		let (chunk_size, size) = (self.source.chunk_size as u64, self.source.resource.size);
+		
+		// Collect the chunks that overlap with `ranges` (but not more than the cache can hold, because
+		// the first chunks would be evicted again before they are read)
+		let mut offsets = std::collections::BTreeSet::new();
+		for &(offset, length) in ranges.iter().filter(|&&(offset, length)| length > 0 && offset < size) {
+			let end = std::cmp::min(offset.saturating_add(length as u64), size);
+			let mut aligned_offset = (offset / chunk_size) * chunk_size;
+			while aligned_offset < end { offsets.insert(aligned_offset); aligned_offset += chunk_size }
+		}
+		
+		// Filter the cached chunks and load the chunks from the disk-cache (using `chunk_buf`)
+		let mut missing = Vec::new();
+		self.chunk_buf_offset = None;
+		for aligned_offset in offsets.into_iter().take(std::cmp::max(self.cache_chunk_count(), 1)) {
+			let length = self.source.chunk_length(aligned_offset);
+			if self.source.is_cached(aligned_offset) || self.source.load_from_disk(aligned_offset, &mut self.chunk_buf[.. length]) { continue }
+			missing.push(aligned_offset)
+		}
+		
+		// Coalesce adjacent chunks into ranges (first and last byte)
+		let mut coalesced: Vec<(u64, u64)> = Vec::new();
+		for aligned_offset in missing.iter().cloned() {
+			let last = std::cmp::min(aligned_offset + chunk_size, size) - 1;
+			match coalesced.last_mut() {
+				Some(range) if range.1 + 1 == aligned_offset => range.1 = last,
+				_ => coalesced.push((aligned_offset, last))
+			}
+		}
+		
+		// Fetch the ranges in batches and store all chunks that are entirely contained in a part
+		let mut fetched = std::collections::HashSet::new();
+		for batch in coalesced.chunks(MAX_RANGES_PER_REQUEST) {
+			let (options, resource) = (&self.source.options, &self.source.resource);
+			let parts = self.source.pool.with_connection(&mut self.uri, options, super::time_remaining(timeout_point), |uri, connection| {
+				super::http_action::fetch_ranges(uri, connection, options, resource, batch, super::time_remaining(timeout_point))
+			})?;
+			
+			// Stop if the server refuses multi-range-requests
+			let parts = match parts {
+				Some(parts) => parts,
+				None => break
+			};
+			for (first, data) in parts {
+				let last = first + data.len() as u64;
+				let mut aligned_offset = ((first + chunk_size - 1) / chunk_size) * chunk_size;
+				while aligned_offset < last {
+					let chunk_end = std::cmp::min(aligned_offset + chunk_size, size);
+					if chunk_end > last { break }
+					
+					self.source.insert(aligned_offset, &data[(aligned_offset - first) as usize .. (chunk_end - first) as usize]);
+					fetched.insert(aligned_offset);
+					aligned_offset += chunk_size
+				}
+			}
+		}
 		
-		// Check if we have the chunk or if we neet to fetch the chunk
-		if self.cache.contains(aligned_offset) {
-			self.chunk_buf.copy_from_slice(self.cache.get(aligned_offset));
+		// Fetch the remaining chunks separately
+		let remaining = missing.into_iter().filter(|offset| !fetched.contains(offset)).map(|offset| (offset, 0, &mut [][..])).collect();
+		self.fetch_chunks(remaining, super::time_remaining(timeout_point))
+	}
+	
+	/// Reads the `(aligned_offset, skip, buffer)`-targets concurrently over up to `max_connections`
+	/// pooled connections
+	///
+	/// _Note: Chunks that are entirely covered by their buffer are received directly into the buffer
+	/// and copied into the cache from there; targets with an empty buffer are only fetched into the
+	/// cache_
+	fn fetch_chunks(&self, targets: Vec<(u64, usize, &mut[u8])>, timeout: std::time::Duration) -> Result<(), Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		if targets.is_empty() { return Ok(()) }
+		
+		// Distribute the targets over the workers
+		let worker_count = std::cmp::min(targets.len(), self.source.pool.max_connections());
+		let mut groups: Vec<Vec<_>> = (0..worker_count).map(|_| Vec::new()).collect();
+		for (i, target) in targets.into_iter().enumerate() { groups[i % worker_count].push(target) }
+		
+		// Fetch the chunks on the current thread if there is only one worker
+		let (uri, source) = (&self.uri, &self.source);
+		let fetch_group = move |group: Vec<(u64, usize, &mut[u8])>| -> Result<(), Error> {
+			let (mut uri, mut chunk_buf) = (uri.clone(), Vec::new());
+			for (aligned_offset, skip, buffer) in group {
+				// Serve cache-hits
+				let hit = match buffer.is_empty() {
+					true => source.is_cached(aligned_offset),
+					false => source.read_cached(aligned_offset, skip, buffer)
+				};
+				if hit { continue }
+				
+				// Receive the chunk directly into `buffer` if it covers the entire chunk
+				let length = source.chunk_length(aligned_offset);
+				if buffer.len() == length {
+					source.load(&mut uri, aligned_offset, buffer, super::time_remaining(timeout_point))?;
+					continue
+				}
+				
+				chunk_buf.resize(length, 0);
+				source.load(&mut uri, aligned_offset, &mut chunk_buf, super::time_remaining(timeout_point))?;
+				buffer.copy_from_slice(&chunk_buf[skip .. skip + buffer.len()]);
+			}
 			Ok(())
-		} else {
-			super::http_action::receive_chunk(&self.uri, &mut self.connection, &mut self.chunk_buf[..chunk_size], aligned_offset, timeout)?;
-			self.cache.insert(&self.chunk_buf, aligned_offset);
+		};
+		if worker_count == 1 { return fetch_group(groups.pop().unwrap()) }
+		
+		// Fetch the chunks concurrently
+		std::thread::scope(|scope| {
+			let workers: Vec<_> = groups.into_iter().map(|group| scope.spawn(move || fetch_group(group))).collect();
+			
+			// Join the workers and propagate the first error
+			for worker in workers { worker.join().unwrap()? }
 			Ok(())
+		})
+	}
+}
+
+impl std::io::Read for File {
+	fn read(&mut self, buffer: &mut[u8]) -> std::io::Result<usize> {
+		let timeout = self.io_timeout;
+		Ok(File::read(self, buffer, timeout)?)
+	}
+}
+impl std::io::Seek for File {
+	fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+		// Compute the absolute target-position
+		let target = match position {
+			std::io::SeekFrom::Start(offset) => offset as i128,
+			std::io::SeekFrom::End(by) => self.source.resource.size as i128 + by as i128,
+			std::io::SeekFrom::Current(by) => self.position as i128 + by as i128
+		};
+		
+		// Validate boundaries and apply position
+		if target < 0 { return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot seek before 0")) }
+		if target > self.source.resource.size as i128 { return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Cannot seek beyond EOF ({})", self.source.resource.size))) }
+		self.position = target as u64;
+		Ok(self.position)
+	}
+}
+impl std::io::BufRead for File {
+	fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+		// Check for EOF
+		if self.position >= self.source.resource.size { return Ok(&[]) }
+		
+		// Fetch the chunk containing the current position
+		let aligned_offset = (self.position / self.source.chunk_size as u64) * self.source.chunk_size as u64;
+		let timeout = self.io_timeout;
+		self.with_reopen(timeout, |file| file.read_chunk(aligned_offset, timeout))?;
+		
+		// Return the remaining part of the chunk
+		let chunk_size = self.source.chunk_length(aligned_offset);
+		Ok(&self.chunk_buf[(self.position - aligned_offset) as usize .. chunk_size])
+	}
+	
+	fn consume(&mut self, amount: usize) {
+		self.position = std::cmp::min(self.position + amount as u64, self.source.resource.size)
+	}
+}
+
+
+#[cfg(test)]
+pub mod tests {
+	use super::File;
+	use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	
+	pub const SIZE: usize = 10_000;
+	pub const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+	
+	/// The test-resource
+	pub fn data() -> Vec<u8> {
+		(0 .. SIZE).map(|i| if i % 71 == 70 { b'\n' } else { b'a' + (i % 26) as u8 }).collect()
+	}
+	
+	/// Answers a single connection with keep-alive (`HEAD` and single-range-`GET`-requests) and counts
+	/// the `GET`-requests
+	fn serve(stream: std::net::TcpStream, requests: &AtomicUsize) -> Result<(), std::io::Error> {
+		let (data, mut reader, mut stream) = (data(), std::io::BufReader::new(stream.try_clone()?), stream);
+		loop {
+			// Read the request-header
+			let mut request_line = String::new();
+			if reader.read_line(&mut request_line)? == 0 { return Ok(()) }
+			let mut range = None;
+			loop {
+				let mut line = String::new();
+				reader.read_line(&mut line)?;
+				if line.trim().is_empty() { break }
+				if line.to_ascii_lowercase().starts_with("range: bytes=") {
+					let mut bounds = line[13..].trim().splitn(2, '-').map(|bound| bound.parse::<usize>().unwrap());
+					range = Some((bounds.next().unwrap(), bounds.next().unwrap()));
+				}
+			}
+			
+			// Write the response
+			if !request_line.starts_with("HEAD ") { requests.fetch_add(1, Ordering::SeqCst); }
+			let response = match (request_line.starts_with("HEAD "), range) {
+				(true, _) => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nETag: \"v1\"\r\n\r\n", SIZE).into_bytes(),
+				(false, Some((first, last))) => {
+					let last = std::cmp::min(last, SIZE - 1);
+					let mut response = format!(
+						"HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nETag: \"v1\"\r\n\r\n",
+						last + 1 - first, first, last, SIZE
+					).into_bytes();
+					response.extend_from_slice(&data[first ..= last]);
+					response
+				},
+				(false, None) => b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n".to_vec()
+			};
+			stream.write_all(&response)?;
 		}
 	}
-}
\ No newline at end of file
+	
+	/// Starts a local server for the test-resource and returns the URL and the `GET`-request-counter
+	pub fn server() -> (String, Arc<AtomicUsize>) {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+		let requests = Arc::new(AtomicUsize::new(0));
+		
+		let requests_ = requests.clone();
+		std::thread::spawn(move || for stream in listener.incoming().flatten() {
+			let requests = requests_.clone();
+			std::thread::spawn(move || serve(stream, &requests));
+		});
+		(url, requests)
+	}
+	
+	/// Starts a local server and opens the test-resource with a cache of 4 chunks à 1000 bytes
+	fn open() -> File {
+		File::open_with_options(&server().0, super::super::OpenOptions::new().timeout(TIMEOUT).cache_size(4, 1000).no_proxy()).unwrap()
+	}
+	
+	#[test]
+	fn read() {
+		let _lock = super::super::cache::tests::LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let mut file = open();
+		assert_eq!((file.size(), file.name()), (SIZE as u64, "file.txt"));
+		
+		// Read across chunk-boundaries and beyond the cache-size
+		let mut buffer = vec![0; 1500];
+		file.read_exact(&mut buffer).unwrap();
+		assert_eq!(&buffer[..], &data()[.. 1500]);
+		let mut rest = Vec::new();
+		file.read_to_end(&mut rest).unwrap();
+		assert_eq!(&rest[..], &data()[1500 ..]);
+		assert_eq!(Read::read(&mut file, &mut buffer).unwrap(), 0);
+		
+		let mut buffer = vec![0; 6000];
+		assert_eq!(file.read_at(&mut buffer, 999, TIMEOUT).unwrap(), 6000);
+		assert_eq!(&buffer[..], &data()[999 .. 6999]);
+		assert_eq!(file.read_at(&mut buffer, 9000, TIMEOUT).unwrap(), 1000);
+	}
+	
+	#[test]
+	fn seek() {
+		let _lock = super::super::cache::tests::LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let mut file = open();
+		assert_eq!(Seek::seek(&mut file, SeekFrom::End(-10)).unwrap(), SIZE as u64 - 10);
+		assert_eq!(Seek::seek(&mut file, SeekFrom::Current(-990)).unwrap(), SIZE as u64 - 1000);
+		assert_eq!(Seek::seek(&mut file, SeekFrom::Start(1234)).unwrap(), 1234);
+		
+		let mut buffer = [0; 10];
+		file.read_exact(&mut buffer).unwrap();
+		assert_eq!(&buffer[..], &data()[1234 .. 1244]);
+		
+		// Seeking before `0` or beyond the `EOF` fails and keeps the position
+		assert_eq!(Seek::seek(&mut file, SeekFrom::Current(-1245)).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+		assert_eq!(Seek::seek(&mut file, SeekFrom::End(1)).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+		assert_eq!(Seek::seek(&mut file, SeekFrom::End(0)).unwrap(), SIZE as u64);
+	}
+	
+	#[test]
+	fn buf_read() {
+		let _lock = super::super::cache::tests::LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let mut file = open();
+		
+		// Read the lines (which span chunk-boundaries)
+		let lines: Vec<String> = (&mut file).lines().map(|line| line.unwrap()).collect();
+		let expected: Vec<String> = String::from_utf8(data()).unwrap().lines().map(|line| line.to_owned()).collect();
+		assert_eq!(lines, expected);
+		assert!(file.fill_buf().unwrap().is_empty());
+		
+		// The buffered chunk is returned until it is consumed
+		Seek::seek(&mut file, SeekFrom::Start(1990)).unwrap();
+		assert_eq!(file.fill_buf().unwrap(), &data()[1990 .. 2000]);
+		assert_eq!(file.chunk_buf_offset, Some(1000));
+		file.consume(4);
+		assert_eq!(file.fill_buf().unwrap(), &data()[1994 .. 2000]);
+		file.consume(6);
+		assert_eq!(file.fill_buf().unwrap(), &data()[2000 .. 3000]);
+	}
+	
+	#[test]
+	fn adjust_cache_size() {
+		let _lock = super::super::cache::tests::LOCK.lock().unwrap_or_else(|error| error.into_inner());
+		let mut file = open();
+		Seek::seek(&mut file, SeekFrom::Start(500)).unwrap();
+		assert_eq!(file.fill_buf().unwrap(), &data()[500 .. 1000]);
+		
+		// A chunk-size of `0` is rejected
+		assert!(file.adjust_cache_size(4, 0).is_err());
+		assert_eq!(file.fill_buf().unwrap(), &data()[500 .. 1000]);
+		
+		// Changing the chunk-size invalidates the buffered chunk
+		file.adjust_cache_size(2, 300).unwrap();
+		assert_eq!(file.fill_buf().unwrap(), &data()[500 .. 600]);
+		assert_eq!(file.cache_chunk_count(), 2);
+	}
+}
@@ -2,6 +2,124 @@ use std;
 use super::error::{Error, ErrorType};
 use super::http;
 
+/// The result of a request that might have been redirected
+pub enum Redirectable<T> {
+	/// The request was successful
+	Done(T),
+	/// The server redirected the request to the contained (possibly relative) location
+	Redirect(String)
+}
+
+/// Information about the remote resource
+#[derive(Debug, Clone)]
+pub struct ResourceInfo {
+	/// The resource-size
+	pub size: u64,
+	/// The `ETag`-field (if any)
+	pub etag: Option<String>,
+	/// The `Last-Modified`-field (if any)
+	pub last_modified: Option<String>
+}
+impl ResourceInfo {
+	/// Returns a validator that identifies the resource-version (the `ETag` or the
+	/// `Last-Modified`-date)
+	pub fn validator(&self) -> Option<&str> {
+		self.etag.as_ref().or(self.last_modified.as_ref()).map(|validator| validator.as_str())
+	}
+}
+
+pub fn redirect_location(response: &http::ResponseHeader) -> Result<Option<String>, Error> {
+	match response.http_status_code_reason.0 {
+		301 | 302 | 303 | 307 | 308 => match response.header_fields.get("Location") {
+			Some(location) => Ok(Some(location.clone())),
+			None => throw_err!(ErrorType::InvalidData, "The server sent a redirect without \"Location\"-field".to_owned())
+		},
+		_ => Ok(None)
+	}
+}
+
+/// Creates the error for an unexpected response-status
+pub fn status_error<T>(response: &http::ResponseHeader) -> Result<T, Error> {
+	let (code, ref reason) = response.http_status_code_reason;
+	new_err!(ErrorType::HttpStatus{ code, reason: reason.clone(), headers: response.header_fields.clone() }, format!("HTTP-error {}: {}", code, reason))
+}
+
+/// Parses a `Retry-After`-field (delay-seconds or a HTTP-date) into the delay
+fn parse_retry_after(field: &str) -> Option<std::time::Duration> {
+	let field = field.trim();
+	if let Ok(seconds) = field.parse::<u64>() { return Some(std::time::Duration::from_secs(seconds)) }
+	
+	// A date in the past (e.g. due to clock-skew) means "retry now"
+	let date = parse_http_date(field)?;
+	Some(date.duration_since(std::time::SystemTime::now()).unwrap_or_default())
+}
+
+/// Parses a HTTP-date (the IMF-fixdate- and the obsolete RFC-850- and asctime-formats)
+fn parse_http_date(date: &str) -> Option<std::time::SystemTime> {
+	const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+	
+	// Tokenize the date (in all formats the first number is the day and the second one the year)
+	let (mut month, mut time, mut numbers) = (None, None, Vec::new());
+	for token in date.split(&[' ', ',', '-'][..]).filter(|token| !token.is_empty()) {
+		if let Some(index) = MONTHS.iter().position(|name| token.eq_ignore_ascii_case(name)) { month = Some(index as u64 + 1) }
+			else if token.contains(':') { time = Some(token) }
+			else if let Ok(number) = token.parse::<u64>() { numbers.push(number) }
+	}
+	let (month, time) = (month?, time?);
+	let (day, year) = match *numbers.as_slice() {
+		[day, year] if (1 ..= 31).contains(&day) => (day, match year { 0 ..= 69 => year + 2000, 70 ..= 99 => year + 1900, _ => year }),
+		_ => return None
+	};
+	if !(1970 ..= 9999).contains(&year) { return None }
+	
+	let time: Vec<u64> = time.split(':').map(|part| part.parse::<u64>().ok()).collect::<Option<_>>()?;
+	let (hour, minute, second) = match *time.as_slice() {
+		[hour, minute, second] if hour < 24 && minute < 60 && second <= 60 => (hour, minute, second),
+		_ => return None
+	};
+	
+	// Compute the days since the epoch (see http://howardhinnant.github.io/date_algorithms.html)
+	let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+	let (era, year_of_era) = (year / 400, year % 400);
+	let day_of_year = (153 * month + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	let days = (era * 146097 + day_of_era).checked_sub(719468)?;
+	
+	let seconds = days.checked_mul(86400)?.checked_add(hour * 3600 + minute * 60 + second)?;
+	std::time::UNIX_EPOCH.checked_add(std::time::Duration::from_secs(seconds))
+}
+
+/// Returns the `Retry-After`-delay (if any) and the cause that is passed to the retry-hook if the
+/// response is a retryable throttling-response (`429`/`503` by default)
+pub fn throttling(response: &http::ResponseHeader, options: &super::OpenOptions) -> Option<(Option<std::time::Duration>, std::io::Error)> {
+	let (code, ref reason) = response.http_status_code_reason;
+	if !options.retry_policy.is_retryable_status(code) { return None }
+	
+	let retry_after = response.header_fields.get("Retry-After").and_then(|field| parse_retry_after(field));
+	Some((retry_after, std::io::Error::new(std::io::ErrorKind::Other, format!("HTTP-error {}: {}", code, reason))))
+}
+
+/// Creates the error for a server that is still throttling the requests after `cause`
+pub fn throttled_error<T>(cause: &std::io::Error) -> Result<T, Error> {
+	new_err!(ErrorType::Throttled, format!("The server is still throttling the requests ({})", cause))
+}
+
+/// Handles a throttling-response: waits for the `Retry-After`-delay (or the backoff-delay) and
+/// reconnects; returns `false` if the response-status is not retryable
+fn throttle(response: &http::ResponseHeader, connection: &mut super::Connection, options: &super::OpenOptions, retrier: &mut super::retry::Retrier, timeout_point: std::time::Instant) -> Result<bool, Error> {
+	let (retry_after, cause) = match throttling(response, options) {
+		Some(throttling) => throttling,
+		None => return Ok(false)
+	};
+	
+	// Wait (if the deadline allows it)
+	if !retrier.throttled(retry_after, &cause, timeout_point) { return throttled_error(&cause) }
+	
+	// The response may have a body, so we use a new connection for the retry
+	connection.reconnect(super::time_remaining(timeout_point))?;
+	Ok(true)
+}
+
 fn http_request_response(request: http::RequestHeader, connection: &mut super::Connection, timeout_point: std::time::Instant) -> Result<http::ResponseHeader, std::io::Error> {
 	use http::{WriteableHeader, ReadableHeader};
 	
@@ -28,94 +146,596 @@ fn http_response_body(buffer: &mut[u8], connection: &mut super::Connection, time
 
 
 
-pub fn receive_size(uri: &super::URI, connection: &mut super::Connection, timeout: std::time::Duration) -> Result<u64, Error> {
-	let timeout_point = std::time::Instant::now() + timeout;
-	
-	// Build HTTP-request
+pub fn authorize(request: &mut http::RequestHeader, uri: &super::URI, options: &super::OpenOptions) {
+	if let Some(authorization) = options.auth_state.authorization(&options.credentials, uri, &request.http_method, &request.request_uri) {
+		request.header_fields.insert("Authorization".to_owned(), authorization);
+	}
+}
+
+/// Builds a request for `uri`; if it is forwarded by `proxy`, the absolute URL is used as
+/// request-target and the `Proxy-Authorization`-field is added
+pub fn build_request(http_method: &str, uri: &super::URI, proxy: Option<&super::Proxy>, options: &super::OpenOptions) -> http::RequestHeader {
+	let forwarded_by = proxy.filter(|proxy| proxy.forwards(&uri.protocol));
 	let mut request = http::RequestHeader::default();
-	request.http_method = "HEAD".to_owned();
-	request.request_uri = uri.resource.clone();
+	request.http_method = http_method.to_owned();
+	request.request_uri = if forwarded_by.is_some() { uri.url.clone() } else { uri.resource.clone() };
+	
+	// Insert the custom fields (they never contain the protected fields and might contain credentials,
+	// so they are not sent to other servers)
+	let is_origin = options.auth_state.is_origin(uri);
+	for &(ref name, ref value) in options.headers.iter() {
+		if is_origin || super::options::is_cross_origin_field(name) { request.header_fields.insert(name.clone(), value.clone()); }
+	}
+	
 	request.header_fields.insert("Host".to_owned(), uri.server.clone());
 	request.header_fields.insert("Content-Length".to_owned(), "0".to_owned());
 	request.header_fields.insert("Connection".to_owned(), "keep-alive".to_owned());
+	if let Some(authorization) = forwarded_by.and_then(|proxy| proxy.authorization()) { request.header_fields.insert("Proxy-Authorization".to_owned(), authorization); }
+	request
+}
+
+
+
+pub fn receive_size(uri: &super::URI, connection: &mut super::Connection, options: &super::OpenOptions, timeout: std::time::Duration) -> Result<Redirectable<ResourceInfo>, Error> {
+	let timeout_point = std::time::Instant::now() + timeout;
+	
+	// Build HTTP-request
+	let mut request = build_request("HEAD", uri, connection.proxy(), options);
 	
 	// Try to send HTTP-request
+	let (mut retrier, mut stale_retries) = (options.retry_policy.retrier(super::RetryOperation::Request), 0);
 	'retry_loop: while super::time_remaining(timeout_point) > std::time::Duration::default() {
 		// Send request-header and receive response-header
+		authorize(&mut request, uri, options);
 		let response = match http_request_response(request.clone(), connection, timeout_point) {
 			Ok(response) => response,
-			Err(ref error) if super::Connection::is_recoverable(error) => {
+			Err(ref error) if retrier.retry(error, timeout_point) => {
 				connection.reconnect(super::time_remaining(timeout_point))?;
 				continue 'retry_loop
 			},
-			Err(error) => throw_err!(ErrorType::from(error))
+			Err(error) => return Err(Error::from(error))
 		};
 		
 		// Parse response
-		if response.http_status_code_reason.0 != 200 { throw_err!(ErrorType::IOAccessError, format!("HTTP-error {}: {}", response.http_status_code_reason.0, &response.http_status_code_reason.1)) }
-		
-		let accept_ranges = if let Some(accept_ranges) = response.header_fields.get("Accept-Ranges") { accept_ranges }
-			else { throw_err!(ErrorType::Unsupported, "The server does not support partial-content-requests".to_owned()) };
-		if accept_ranges != "bytes" { throw_err!(ErrorType::Unsupported, "The server does not support byte-indexed partial-content-requests".to_owned()) }
+		if let Some(location) = redirect_location(&response)? { return Ok(Redirectable::Redirect(location)) }
+		if response.http_status_code_reason.0 == 401 {
+			options.auth_state.challenge(&options.credentials, &request, &response, &mut stale_retries)?;
+			continue 'retry_loop
+		}
+		if throttle(&response, connection, options, &mut retrier, timeout_point)? { continue 'retry_loop }
+		return match parse_head_response(&response, options)? {
+			Some(info) => Ok(Redirectable::Done(info)),
+			None => probe_size(uri, connection, options, super::time_remaining(timeout_point))
+		}
+	};
+	throw_err!(ErrorType::from(std::io::Error::from(std::io::ErrorKind::TimedOut)))
+}
+
+/// Evaluates the final response to a `HEAD`-request; returns `None` if the size must be determined
+/// using a ranged `GET` (see `probe_size`)
+pub fn parse_head_response(response: &http::ResponseHeader, options: &super::OpenOptions) -> Result<Option<ResourceInfo>, Error> {
+	match response.http_status_code_reason.0 {
+		200 => (),
+		// Some servers forbid or don't implement `HEAD`
+		403 | 405 | 501 => return Ok(None),
+		_ => return status_error(response)
+	}
+	
+	let accept_ranges = match response.header_fields.get("Accept-Ranges") {
+		Some(accept_ranges) => accept_ranges,
+		None if options.probe_ranges => return Ok(None),
+		None => throw_err!(ErrorType::Unsupported, "The server does not support partial-content-requests".to_owned())
+	};
+	if accept_ranges != "bytes" { throw_err!(ErrorType::Unsupported, "The server does not support byte-indexed partial-content-requests".to_owned()) }
+	
+	// Some servers answer `HEAD` with chunked transfer-encoding and without a `Content-Length`
+	let length_field = match response.header_fields.get("Content-Length") {
+		Some(length_field) => length_field,
+		None => return Ok(None)
+	};
+	let length = try_err!(length_field.parse::<u64>(), "The server returned an invalid \"Content-Length\"-field".to_owned());
+	
+	let (etag, last_modified) = (response.header_fields.get("ETag").cloned(), response.header_fields.get("Last-Modified").cloned());
+	Ok(Some(ResourceInfo{ size: length, etag, last_modified }))
+}
+
+
+
+/// Adds preconditions to make sure that all chunks belong to the same resource-version (weak ETags
+/// cannot be used because `If-Match` and `If-Range` require a strong comparison); returns `true` if
+/// a precondition was added
+pub fn add_preconditions(request: &mut http::RequestHeader, resource: &ResourceInfo) -> bool {
+	match (&resource.etag, &resource.last_modified) {
+		(&Some(ref etag), _) if !etag.starts_with("W/") => {
+			request.header_fields.insert("If-Match".to_owned(), etag.clone());
+			request.header_fields.insert("If-Range".to_owned(), etag.clone());
+			true
+		},
+		(_, &Some(ref last_modified)) => {
+			request.header_fields.insert("If-Range".to_owned(), last_modified.clone());
+			true
+		},
+		_ => false
+	}
+}
+
+/// Parses a `Content-Range`-field (`bytes first-last/total` or `bytes */total`) into the range
+/// (if any) and the total size (if known)
+fn parse_content_range(field: &str) -> Option<(Option<(u64, u64)>, Option<u64>)> {
+	let field = field.trim();
+	if !field.starts_with("bytes ") { return None }
+	
+	let mut parts = field["bytes ".len() ..].trim().splitn(2, '/');
+	let (range, total) = (parts.next()?.trim(), parts.next()?.trim());
+	let range = match range {
+		"*" => None,
+		range => {
+			let mut bounds = range.splitn(2, '-');
+			let (first, last) = (bounds.next()?.trim().parse::<u64>().ok()?, bounds.next()?.trim().parse::<u64>().ok()?);
+			// A range that ends at `u64::MAX` cannot be valid because the length would overflow
+			if first > last || last == u64::MAX { return None }
+			Some((first, last))
+		}
+	};
+	let total = match total {
+		"*" => None,
+		total => Some(total.parse::<u64>().ok()?)
+	};
+	Some((range, total))
+}
+
+/// Determines the resource-size using a ranged `GET` for the first byte (this is used if the server
+/// does not advertise range-support, forbids `HEAD` or does not send a `Content-Length`)
+fn probe_size(uri: &super::URI, connection: &mut super::Connection, options: &super::OpenOptions, timeout: std::time::Duration) -> Result<Redirectable<ResourceInfo>, Error> {
+	let timeout_point = std::time::Instant::now() + timeout;
+	
+	// Build HTTP-request
+	let mut request = build_request("GET", uri, connection.proxy(), options);
+	request.header_fields.insert("Range".to_owned(), "bytes=0-0".to_owned());
+	
+	// Try to send HTTP-request
+	let (mut retrier, mut stale_retries) = (options.retry_policy.retrier(super::RetryOperation::Request), 0);
+	'retry_loop: while super::time_remaining(timeout_point) > std::time::Duration::default() {
+		// Send request-header and receive response-header
+		authorize(&mut request, uri, options);
+		let response = match http_request_response(request.clone(), connection, timeout_point) {
+			Ok(response) => response,
+			Err(ref error) if retrier.retry(error, timeout_point) => {
+				connection.reconnect(super::time_remaining(timeout_point))?;
+				continue 'retry_loop
+			},
+			Err(error) => return Err(Error::from(error))
+		};
 		
-		let length_field = if let Some(length_field) = response.header_fields.get("Content-Length") { length_field }
-			else { throw_err!(ErrorType::Unsupported, "The server did not send a \"Content-Length\"-field".to_owned()) };
-		let length = try_err!(length_field.parse::<u64>(), "The server returned an invalid \"Content-Length\"-field".to_owned());
+		// Parse the response (the connection is reestablished if we don't consume the body)
+		if let Some(location) = redirect_location(&response)? {
+			connection.reconnect(super::time_remaining(timeout_point))?;
+			return Ok(Redirectable::Redirect(location))
+		}
+		if response.http_status_code_reason.0 == 401 {
+			options.auth_state.challenge(&options.credentials, &request, &response, &mut stale_retries)?;
+			connection.reconnect(super::time_remaining(timeout_point))?;
+			continue 'retry_loop
+		}
+		if throttle(&response, connection, options, &mut retrier, timeout_point)? { continue 'retry_loop }
 		
-		return Ok(length)
+		// Consume the body and reuse the connection if possible
+		let (info, body_length) = parse_probe_response(&response)?;
+		match body_length {
+			Some(length) => if let Err(error) = http_response_body(&mut vec![0u8; length as usize], connection, timeout_point) {
+				if !options.retry_policy.is_retryable(error.kind()) { return Err(Error::from(error)) }
+				connection.reconnect(super::time_remaining(timeout_point))?;
+			},
+			None => connection.reconnect(super::time_remaining(timeout_point))?
+		}
+		return Ok(Redirectable::Done(info))
 	};
 	throw_err!(ErrorType::from(std::io::Error::from(std::io::ErrorKind::TimedOut)))
 }
 
+/// Evaluates the final response to the ranged `GET` of `probe_size`; returns the resource-
+/// information and the body-length if the body is small enough to be consumed (otherwise the
+/// connection must be reestablished)
+pub fn parse_probe_response(response: &http::ResponseHeader) -> Result<(ResourceInfo, Option<u64>), Error> {
+	let (etag, last_modified) = (response.header_fields.get("ETag").cloned(), response.header_fields.get("Last-Modified").cloned());
+	let content_range = response.header_fields.get("Content-Range").and_then(|field| parse_content_range(field));
+	match (response.http_status_code_reason.0, content_range) {
+		// The server honours ranges
+		(206, Some((Some((first, last)), Some(size)))) => {
+			let body_length = response.header_fields.get("Content-Length").and_then(|length| length.parse::<u64>().ok())
+				.filter(|&length| length == last - first + 1 && length <= 65536);
+			Ok((ResourceInfo{ size, etag, last_modified }, body_length))
+		},
+		// The resource is empty
+		(416, Some((None, Some(0)))) => Ok((ResourceInfo{ size: 0, etag, last_modified }, None)),
+		(200, _) => throw_err!(ErrorType::Unsupported, "The server does not support partial-content-requests".to_owned()),
+		(206, _) => throw_err!(ErrorType::Unsupported, "The server did not send a usable \"Content-Range\"-field".to_owned()),
+		_ => status_error(response)
+	}
+}
+
 
 
-pub fn receive_chunk(uri: &super::URI, connection: &mut super::Connection, buffer: &mut[u8], file_offset: u64, timeout: std::time::Duration) -> Result<(), Error> {
+pub fn receive_chunk(uri: &super::URI, connection: &mut super::Connection, options: &super::OpenOptions, resource: &ResourceInfo, buffer: &mut[u8], file_offset: u64, timeout: std::time::Duration) -> Result<Redirectable<()>, Error> {
 	let timeout_point = std::time::Instant::now() + timeout;
 	
 	// Check buffer-length
-	if buffer.len() == 0 { return Ok(()) }
+	if buffer.len() == 0 { return Ok(Redirectable::Done(())) }
 	
 	// Build HTTP-request
-	let mut request = http::RequestHeader::default();
-	request.http_method = "GET".to_owned();
-	request.request_uri = uri.resource.clone();
-	request.header_fields.insert("Host".to_owned(), uri.server.clone());
-	request.header_fields.insert("Content-Length".to_owned(), "0".to_owned());
-	request.header_fields.insert("Connection".to_owned(), "keep-alive".to_owned());
+	let mut request = build_request("GET", uri, connection.proxy(), options);
 	request.header_fields.insert("Range".to_owned(), format!("bytes={}-{}", file_offset, (file_offset + buffer.len() as u64) - 1));
 	
+	let conditional = add_preconditions(&mut request, resource);
+	
 	// Try to send HTTP-request and receive the response-header and -body `retries`-times
+	let (mut retrier, mut stale_retries) = (options.retry_policy.retrier(super::RetryOperation::Request), 0);
 	'retry_loop: while super::time_remaining(timeout_point) > std::time::Duration::default() {
 		// Send request-header and receive response-header
+		authorize(&mut request, uri, options);
 		let response = match http_request_response(request.clone(), connection, timeout_point) {
 			Ok(response) => response,
-			Err(ref error) if super::Connection::is_recoverable(error) => {
+			Err(ref error) if retrier.retry(error, timeout_point) => {
 				connection.reconnect(super::time_remaining(timeout_point))?;
 				continue 'retry_loop
 			},
-			Err(error) => {
-				let description = format!("{:?}", &error);
-				throw_err!(ErrorType::from(error), description)
-			}
+			Err(error) => return Err(Error::from(error))
 		};
 		
 		// Parse response
-		if response.http_status_code_reason.0 != 206 { throw_err!(ErrorType::IOAccessError, format!("HTTP-error {}: {}", response.http_status_code_reason.0, &response.http_status_code_reason.1)) }
-		
-		let range = if let Some(accept_ranges) = response.header_fields.get("Content-Range") { accept_ranges }
-			else { throw_err!(ErrorType::Unsupported, "The server did not respond with a chunk".to_owned()) };
-		let served_range = super::substring_until_pattern(&range, &mut 0, "/", true)?;
-		if served_range != format!("bytes {}-{}", file_offset, (file_offset + buffer.len() as u64) - 1) { throw_err!(ErrorType::InvalidData, "The server send a chunk with unexpected length".to_owned()) }
+		if let Some(location) = redirect_location(&response)? { return Ok(Redirectable::Redirect(location)) }
+		if response.http_status_code_reason.0 == 401 {
+			// The challenge-response may have a body, so we use a new connection for the retry
+			options.auth_state.challenge(&options.credentials, &request, &response, &mut stale_retries)?;
+			connection.reconnect(super::time_remaining(timeout_point))?;
+			continue 'retry_loop
+		}
+		if throttle(&response, connection, options, &mut retrier, timeout_point)? { continue 'retry_loop }
+		check_chunk_response(&response, resource, file_offset, buffer.len(), conditional)?;
 		
 		// Receive response-body
 		match http_response_body(buffer, connection, timeout_point) {
-			Ok(_) => return Ok(()),
-			Err(ref error) if super::Connection::is_recoverable(error) => {
+			Ok(_) => return Ok(Redirectable::Done(())),
+			Err(ref error) if retrier.retry(error, timeout_point) => {
 				connection.reconnect(super::time_remaining(timeout_point))?;
 				continue 'retry_loop
 			},
-			Err(error) => throw_err!(ErrorType::from(error))
+			Err(error) => return Err(Error::from(error))
 		}
 	};
 	throw_err!(ErrorType::from(std::io::Error::from(std::io::ErrorKind::TimedOut)))
-}
\ No newline at end of file
+}
+
+
+/// Checks that the final response to a ranged `GET` contains the `length` bytes of `resource` at
+/// `file_offset` (`conditional` indicates that preconditions were sent)
+pub fn check_chunk_response(response: &http::ResponseHeader, resource: &ResourceInfo, file_offset: u64, length: usize, conditional: bool) -> Result<(), Error> {
+	match response.http_status_code_reason.0 {
+		// `If-Match` failed or `If-Range` failed and the server sent the entire (new) resource
+		412 => throw_err!(ErrorType::ResourceChanged, "The resource has changed (precondition failed)".to_owned()),
+		200 if conditional => throw_err!(ErrorType::ResourceChanged, "The resource has changed (the server ignored \"If-Range\")".to_owned()),
+		_ => ()
+	}
+	if let (Some(etag), &Some(ref expected)) = (response.header_fields.get("ETag"), &resource.etag) {
+		if etag != expected { throw_err!(ErrorType::ResourceChanged, format!("The resource has changed (ETag {} instead of {})", etag, expected)) }
+	}
+	if response.http_status_code_reason.0 != 206 { return status_error(response) }
+	
+	let (range, total) = match response.header_fields.get("Content-Range").and_then(|field| parse_content_range(field)) {
+		Some(content_range) => content_range,
+		None => throw_err!(ErrorType::Unsupported, "The server did not respond with a chunk".to_owned())
+	};
+	if range != Some((file_offset, (file_offset + length as u64) - 1)) { throw_err!(ErrorType::InvalidData, "The server send a chunk with unexpected length".to_owned()) }
+	if let Some(total) = total {
+		if total != resource.size { throw_err!(ErrorType::ResourceChanged, format!("The resource has changed (size {} instead of {})", total, resource.size)) }
+	}
+	Ok(())
+}
+
+
+
+/// Extracts the (possibly quoted) boundary from a `Content-Type`-field
+fn parse_boundary(content_type: &str) -> Option<String> {
+	content_type.split(';').map(|param| param.trim())
+		.find(|param| param.to_ascii_lowercase().starts_with("boundary="))
+		.map(|param| param["boundary=".len() ..].trim_matches('"').to_owned())
+}
+
+/// Parses a `multipart/byteranges`-body into the parts (offset, data and the total size if known)
+fn parse_byteranges(body: &[u8], boundary: &str) -> Option<Vec<(u64, Vec<u8>, Option<u64>)>> {
+	let find = |haystack: &[u8], needle: &[u8], from: usize| -> Option<usize> {
+		haystack.get(from..)?.windows(needle.len()).position(|window| window == needle).map(|pos| from + pos)
+	};
+	let delimiter = format!("--{}", boundary).into_bytes();
+	
+	let (mut parts, mut pos) = (Vec::new(), find(body, &delimiter, 0)? + delimiter.len());
+	loop {
+		// Check for the closing delimiter
+		if body.get(pos .. pos + 2)? == b"--" { return Some(parts) }
+		
+		// Parse the part-header
+		let header_end = find(body, b"\r\n\r\n", pos)?;
+		let header = std::str::from_utf8(&body[pos .. header_end]).ok()?;
+		let content_range = header.split("\r\n").filter_map(|line| {
+			let separator = line.find(':')?;
+			if line[..separator].trim().eq_ignore_ascii_case("Content-Range") { Some(line[separator + 1 ..].trim()) } else { None }
+		}).next()?;
+		let (first, last, total) = match parse_content_range(content_range)? {
+			(Some((first, last)), total) => (first, last, total),
+			_ => return None
+		};
+		
+		// Copy the data and skip to the next delimiter
+		let data_start = header_end + 4;
+		let data_end = data_start.checked_add((last - first + 1) as usize)?;
+		parts.push((first, body.get(data_start .. data_end)?.to_vec(), total));
+		pos = find(body, &delimiter, data_end)? + delimiter.len();
+	}
+}
+
+/// Receives multiple `ranges` (first and last byte) using a single request
+///
+/// Returns `None` if the server refuses multi-range-requests (i.e. the ranges must be fetched
+/// separately); otherwise the received parts (the server might coalesce ranges, so the parts are
+/// not necessarily equal to `ranges`)
+pub fn receive_ranges(uri: &super::URI, connection: &mut super::Connection, options: &super::OpenOptions, resource: &ResourceInfo, ranges: &[(u64, u64)], timeout: std::time::Duration) -> Result<Redirectable<Option<Vec<(u64, Vec<u8>)>>>, Error> {
+	let timeout_point = std::time::Instant::now() + timeout;
+	
+	// Compute the maximum body-size we accept (the requested data plus the multipart-overhead)
+	let requested: u64 = ranges.iter().map(|&(first, last)| last - first + 1).sum();
+	let limit = requested + 1024 * ranges.len() as u64 + 65536;
+	
+	// Build HTTP-request
+	let ranges_field: Vec<String> = ranges.iter().map(|&(first, last)| format!("{}-{}", first, last)).collect();
+	let mut request = build_request("GET", uri, connection.proxy(), options);
+	request.header_fields.insert("Range".to_owned(), format!("bytes={}", ranges_field.join(",")));
+	let conditional = add_preconditions(&mut request, resource);
+	
+	// Try to send HTTP-request and receive the response-header and -body
+	let (mut retrier, mut stale_retries) = (options.retry_policy.retrier(super::RetryOperation::Request), 0);
+	'retry_loop: while super::time_remaining(timeout_point) > std::time::Duration::default() {
+		// Send request-header and receive response-header
+		authorize(&mut request, uri, options);
+		let response = match http_request_response(request.clone(), connection, timeout_point) {
+			Ok(response) => response,
+			Err(ref error) if retrier.retry(error, timeout_point) => {
+				connection.reconnect(super::time_remaining(timeout_point))?;
+				continue 'retry_loop
+			},
+			Err(error) => return Err(Error::from(error))
+		};
+		
+		// Parse response (the connection is reestablished if we don't consume the body)
+		if let Some(location) = redirect_location(&response)? {
+			connection.reconnect(super::time_remaining(timeout_point))?;
+			return Ok(Redirectable::Redirect(location))
+		}
+		if throttle(&response, connection, options, &mut retrier, timeout_point)? { continue 'retry_loop }
+		match response.http_status_code_reason.0 {
+			401 => {
+				options.auth_state.challenge(&options.credentials, &request, &response, &mut stale_retries)?;
+				connection.reconnect(super::time_remaining(timeout_point))?;
+				continue 'retry_loop
+			},
+			412 => throw_err!(ErrorType::ResourceChanged, "The resource has changed (precondition failed)".to_owned()),
+			200 if conditional => {
+				connection.reconnect(super::time_remaining(timeout_point))?;
+				throw_err!(ErrorType::ResourceChanged, "The resource has changed (the server ignored \"If-Range\")".to_owned())
+			},
+			// The server refuses multi-range-requests
+			200 | 416 => {
+				connection.reconnect(super::time_remaining(timeout_point))?;
+				return Ok(Redirectable::Done(None))
+			},
+			_ => ()
+		}
+		if let (Some(etag), &Some(ref expected)) = (response.header_fields.get("ETag"), &resource.etag) {
+			if etag != expected { throw_err!(ErrorType::ResourceChanged, format!("The resource has changed (ETag {} instead of {})", etag, expected)) }
+		}
+		if response.http_status_code_reason.0 != 206 { return status_error(&response) }
+		
+		// Receive the body if it has a sane size
+		let length = match response.header_fields.get("Content-Length").and_then(|length| length.parse::<u64>().ok()) {
+			Some(length) if length <= limit => length,
+			_ => {
+				connection.reconnect(super::time_remaining(timeout_point))?;
+				return Ok(Redirectable::Done(None))
+			}
+		};
+		let mut body = vec![0u8; length as usize];
+		match http_response_body(&mut body, connection, timeout_point) {
+			Ok(_) => (),
+			Err(ref error) if retrier.retry(error, timeout_point) => {
+				connection.reconnect(super::time_remaining(timeout_point))?;
+				continue 'retry_loop
+			},
+			Err(error) => return Err(Error::from(error))
+		}
+		
+		// Split the body into parts
+		let content_type = response.header_fields.get("Content-Type").map(|content_type| content_type.as_str()).unwrap_or("");
+		let parts = if content_type.trim().to_ascii_lowercase().starts_with("multipart/byteranges") {
+			match parse_boundary(content_type).and_then(|boundary| parse_byteranges(&body, &boundary)) {
+				Some(parts) => parts,
+				None => throw_err!(ErrorType::InvalidData, "The server sent an invalid \"multipart/byteranges\"-body".to_owned())
+			}
+		} else {
+			// The server coalesced the ranges into a single range
+			match response.header_fields.get("Content-Range").and_then(|field| parse_content_range(field)) {
+				Some((Some((first, last)), total)) if last - first + 1 == length => vec![(first, body, total)],
+				_ => throw_err!(ErrorType::InvalidData, "The server did not send a usable \"Content-Range\"-field".to_owned())
+			}
+		};
+		
+		// Validate the resource-size and the ranges (the parts must be within the resource and overlap
+		// with the requested ranges)
+		let (requested_first, requested_last) = (ranges.iter().map(|range| range.0).min().unwrap_or(0), ranges.iter().map(|range| range.1).max().unwrap_or(0));
+		let mut validated = Vec::with_capacity(parts.len());
+		for (first, data, total) in parts {
+			if let Some(total) = total {
+				if total != resource.size { throw_err!(ErrorType::ResourceChanged, format!("The resource has changed (size {} instead of {})", total, resource.size)) }
+			}
+			
+			let last = first + data.len() as u64 - 1;
+			if last >= resource.size { throw_err!(ErrorType::InvalidData, format!("The server sent a part beyond the end of the resource ({}-{})", first, last)) }
+			if first < requested_first || last > requested_last || !ranges.iter().any(|&(range_first, range_last)| first <= range_last && last >= range_first) {
+				throw_err!(ErrorType::InvalidData, format!("The server sent a part that was not requested ({}-{})", first, last))
+			}
+			validated.push((first, data))
+		}
+		return Ok(Redirectable::Done(Some(validated)))
+	};
+	throw_err!(ErrorType::from(std::io::Error::from(std::io::ErrorKind::TimedOut)))
+}
+
+
+
+/// Receives the resource-information and follows redirects (updating `uri` and `connection`
+/// accordingly)
+pub fn fetch_resource_info(uri: &mut super::URI, connection: &mut super::Connection, options: &super::OpenOptions, timeout: std::time::Duration) -> Result<ResourceInfo, Error> {
+	let timeout_point = std::time::Instant::now() + timeout;
+	
+	let mut redirects = 0;
+	loop {
+		match receive_size(uri, connection, options, super::time_remaining(timeout_point))? {
+			Redirectable::Done(info) => return Ok(info),
+			Redirectable::Redirect(location) => follow_redirect(uri, connection, &location, options, &mut redirects, false, timeout_point)?
+		}
+	}
+}
+
+/// Receives the chunk at `file_offset` into `buffer` and follows redirects (updating `uri` and
+/// `connection` accordingly)
+pub fn fetch_chunk(uri: &mut super::URI, connection: &mut super::Connection, options: &super::OpenOptions, resource: &ResourceInfo, buffer: &mut[u8], file_offset: u64, timeout: std::time::Duration) -> Result<(), Error> {
+	let timeout_point = std::time::Instant::now() + timeout;
+	
+	let mut redirects = 0;
+	loop {
+		match receive_chunk(uri, connection, options, resource, buffer, file_offset, super::time_remaining(timeout_point))? {
+			Redirectable::Done(_) => return Ok(()),
+			Redirectable::Redirect(location) => follow_redirect(uri, connection, &location, options, &mut redirects, true, timeout_point)?
+		}
+	}
+}
+
+/// Receives multiple `ranges` using a single request (see `receive_ranges`) and follows redirects
+/// (updating `uri` and `connection` accordingly)
+pub fn fetch_ranges(uri: &mut super::URI, connection: &mut super::Connection, options: &super::OpenOptions, resource: &ResourceInfo, ranges: &[(u64, u64)], timeout: std::time::Duration) -> Result<Option<Vec<(u64, Vec<u8>)>>, Error> {
+	let timeout_point = std::time::Instant::now() + timeout;
+	
+	let mut redirects = 0;
+	loop {
+		match receive_ranges(uri, connection, options, resource, ranges, super::time_remaining(timeout_point))? {
+			Redirectable::Done(parts) => return Ok(parts),
+			Redirectable::Redirect(location) => follow_redirect(uri, connection, &location, options, &mut redirects, false, timeout_point)?
+		}
+	}
+}
+
+/// Resolves `location` against `uri` and updates `uri` and `connection` accordingly
+///
+/// If the redirect points to another server, a new connection is established; otherwise the
+/// connection is reestablished only if `discard_connection` is set (e.g. because the redirect-
+/// response had a body that was not consumed)
+pub fn follow_redirect(uri: &mut super::URI, connection: &mut super::Connection, location: &str, options: &super::OpenOptions, redirects: &mut usize, discard_connection: bool, timeout_point: std::time::Instant) -> Result<(), Error> {
+	// Check the redirect-limit
+	if *redirects >= options.max_redirects { throw_err!(ErrorType::IOAccessError, format!("Too many redirects (the limit is {})", options.max_redirects)) }
+	*redirects += 1;
+	
+	// Resolve location and connect if necessary
+	let target = uri.resolve(location)?;
+	if target.protocol != uri.protocol || target.server != uri.server {
+		*connection = super::Connection::connect(&target, options, super::time_remaining(timeout_point))?;
+	} else if discard_connection {
+		connection.reconnect(super::time_remaining(timeout_point))?;
+	}
+	*uri = target;
+	Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_retry_after, parse_http_date, parse_content_range, parse_boundary, parse_byteranges};
+	use std::time::{Duration, UNIX_EPOCH};
+	
+	#[test]
+	fn http_date() {
+		// The examples from RFC 9110, section 5.6.7
+		let expected = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+		assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+		assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+		assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+		
+		assert_eq!(parse_http_date("Thu, 29 Feb 2024 23:59:59 GMT"), Some(UNIX_EPOCH + Duration::from_secs(1709251199)));
+		assert_eq!(parse_http_date("Thursday, 01-Jan-70 00:00:00 GMT"), Some(UNIX_EPOCH));
+		assert_eq!(parse_http_date("Fri, 31 Dec 9999 23:59:59 GMT"), Some(UNIX_EPOCH + Duration::from_secs(253402300799)));
+	}
+	
+	#[test]
+	fn http_date_invalid() {
+		let dates = [
+			"", "Sun, 06 Nov 1994", "Sun, 06 Foo 1994 08:49:37 GMT", "Sun, 32 Nov 1994 08:49:37 GMT",
+			"Sun, 06 Nov 1994 24:00:00 GMT", "Sun, 06 Nov 1994 08:49 GMT", "Sun, 06 Nov 1994 08:49:xx GMT",
+			"Sun, 06 Nov 1969 08:49:37 GMT", "Sun, 06 Nov 1994 08:49:37 GMT 7", "Sun Nov 08:49:37 1994",
+			"Sun, 06 Nov 10000 08:49:37 GMT", "Sun, 06 Nov 400000000000 08:49:37 GMT", "Sun, 06 Nov 18446744073709551615 08:49:37 GMT"
+		];
+		for date in dates.iter() { assert_eq!(parse_http_date(date), None, "{}", date) }
+	}
+	
+	#[test]
+	fn retry_after() {
+		assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+		assert_eq!(parse_retry_after(" 0 "), Some(Duration::from_secs(0)));
+		
+		// Dates in the past mean "retry now"
+		assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"), Some(Duration::from_secs(0)));
+		assert!(parse_retry_after("Fri, 31 Dec 2100 23:59:59 GMT").unwrap() > Duration::from_secs(0));
+		
+		for field in ["", "-1", "1.5", "soon", "Sun, 06 Nov 400000000000 08:49:37 GMT"].iter() { assert_eq!(parse_retry_after(field), None, "{}", field) }
+	}
+	
+	#[test]
+	fn content_range() {
+		assert_eq!(parse_content_range("bytes 0-99/1000"), Some((Some((0, 99)), Some(1000))));
+		assert_eq!(parse_content_range(" bytes 64-163/* "), Some((Some((64, 163)), None)));
+		assert_eq!(parse_content_range("bytes */1000"), Some((None, Some(1000))));
+	}
+	
+	#[test]
+	fn content_range_invalid() {
+		for field in ["", "bytes", "items 0-99/1000", "bytes 0-99", "bytes 100-99/1000", "bytes a-b/1000", "bytes 0-99/x", "bytes 0-18446744073709551615/*"].iter() {
+			assert_eq!(parse_content_range(field), None, "{}", field);
+		}
+	}
+	
+	#[test]
+	fn boundary() {
+		assert_eq!(parse_boundary("multipart/byteranges; boundary=3d6b6a416f9b5").as_deref(), Some("3d6b6a416f9b5"));
+		assert_eq!(parse_boundary("multipart/byteranges;charset=utf-8; Boundary=abc").as_deref(), Some("abc"));
+		assert_eq!(parse_boundary("multipart/byteranges; boundary=\"THIS_STRING_SEPARATES\"").as_deref(), Some("THIS_STRING_SEPARATES"));
+		assert_eq!(parse_boundary("multipart/byteranges"), None);
+	}
+	
+	#[test]
+	fn byteranges() {
+		let body = b"This is the preamble\r\n--SEPARATOR\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-4/20\r\n\r\nHello\r\n--SEPARATOR\r\ncontent-range: bytes 10-14/*\r\n\r\nWorld\r\n--SEPARATOR--\r\nThis is the epilogue";
+		let parts = parse_byteranges(body, "SEPARATOR").unwrap();
+		assert_eq!(parts, vec![(0, b"Hello".to_vec(), Some(20)), (10, b"World".to_vec(), None)]);
+	}
+	
+	#[test]
+	fn byteranges_invalid() {
+		// The closing delimiter is missing
+		assert_eq!(parse_byteranges(b"--SEPARATOR\r\nContent-Range: bytes 0-4/20\r\n\r\nHello\r\n", "SEPARATOR"), None);
+		// The part is truncated
+		assert_eq!(parse_byteranges(b"--SEPARATOR\r\nContent-Range: bytes 0-9/20\r\n\r\nHello\r\n--SEPARATOR--", "SEPARATOR"), None);
+		// The part has no `Content-Range` or an unsatisfied range
+		assert_eq!(parse_byteranges(b"--SEPARATOR\r\nContent-Type: text/plain\r\n\r\nHello\r\n--SEPARATOR--", "SEPARATOR"), None);
+		assert_eq!(parse_byteranges(b"--SEPARATOR\r\nContent-Range: bytes */20\r\n\r\n\r\n--SEPARATOR--", "SEPARATOR"), None);
+		// The delimiter does not occur at all or the header is unterminated
+		assert_eq!(parse_byteranges(b"Hello World", "SEPARATOR"), None);
+		assert_eq!(parse_byteranges(b"--SEPARATOR\r\nContent-Range: bytes 0-4/20", "SEPARATOR"), None);
+	}
+}
@@ -1,42 +1,58 @@
 extern crate http;
 extern crate network_io;
+extern crate rustls;
+extern crate rustls_pemfile;
+extern crate webpki_roots;
+extern crate base64;
+extern crate md5;
+extern crate sha2;
+#[cfg(feature = "tokio")] extern crate tokio;
+#[cfg(feature = "tokio")] extern crate tokio_rustls;
 
 #[macro_use] pub mod error;
 mod uri;
+mod tls;
+mod auth;
+mod proxy;
+mod retry;
 mod connection;
+mod pool;
 mod http_action;
 mod cache;
+mod disk_cache;
+mod options;
+mod prefetch;
+mod source;
 pub mod file;
+mod shared;
+#[cfg(feature = "tokio")] mod async_io;
+#[cfg(feature = "tokio")] mod async_connection;
+#[cfg(feature = "tokio")] mod async_action;
+#[cfg(feature = "tokio")] mod async_file;
 
-use uri::URI;
 use connection::Connection;
+use pool::ConnectionPool;
 use cache::CacheDB;
+use disk_cache::DiskCache;
+use prefetch::Prefetcher;
+use source::ChunkSource;
+use http_action::ResourceInfo;
 
 pub use error::{Error, ErrorType};
+pub use uri::{URI, Host};
 pub use file::File;
+pub use shared::SharedFile;
+#[cfg(feature = "tokio")] pub use async_file::{AsyncFile, Open, ReadAt};
+pub use tls::TlsConfig;
+pub use options::OpenOptions;
+pub use auth::Credentials;
+pub use proxy::{Proxy, ProxyProtocol};
+pub use retry::{RetryPolicy, RetryOperation, RetryEvent, RetryHook};
+pub use cache::{CachePolicy, set_cache_memory_budget, cache_memory_usage};
 
 
 
 // Helper functions
-fn substring_until_pattern(string: &str, string_byte_offset: &mut usize, separator: &str, strip_separator: bool) -> Result<String, Error> {
-	// Get substring starting at `string_byte_offset`
-	if *string_byte_offset >= string.len() { throw_err!(ErrorType::InvalidParameter) };
-	let string = string.split_at(*string_byte_offset).1;
-	
-	// Find first occurrence of `separator`
-	let pos = if let Some(pos) = string.find(separator) { pos }
-		else { throw_err!(ErrorType::InvalidParameter) };
-	
-	// Translate `pos` into a byte-offset and adjust
-	let offset = string.char_indices().skip(pos).next().unwrap().0;
-	
-	// Get substring
-	let extracted = string.split_at(offset).0.to_owned();
-	*string_byte_offset += offset + if strip_separator { separator.len() }
-		else { 0 };
-	Ok(extracted)
-}
-
 fn time_remaining(timeout_point: std::time::Instant) -> std::time::Duration {
 	http::time_remaining(timeout_point)
 }
\ No newline at end of file
@@ -0,0 +1,238 @@
+use std;
+use super::error::{Error, ErrorType};
+
+static DEFAULT_TIMEOUT_SECS: u64 = 30;
+pub static DEFAULT_CACHE_CHUNK_SIZE: usize = 131_072;
+pub static DEFAULT_CACHE_CHUNK_COUNT: usize = 2048;
+static DEFAULT_MAX_REDIRECTS: usize = 10;
+static DEFAULT_PREFETCH_WINDOW: usize = 4;
+static DEFAULT_MAX_CONNECTIONS: usize = 4;
+static DEFAULT_USER_AGENT: &str = concat!("http_file/", env!("CARGO_PKG_VERSION"));
+
+/// Header-fields that are managed internally and cannot be set by the user
+static PROTECTED_FIELDS: &[&str] = &["Host", "Range", "Content-Length", "Connection", "If-Match", "If-Range"];
+
+/// Custom header-fields that are also sent to other servers than the origin (e.g. after a
+/// cross-server-redirect); all other custom fields might contain credentials (e.g. `Authorization`,
+/// `Cookie` or API-keys)
+static CROSS_ORIGIN_FIELDS: &[&str] = &["User-Agent"];
+
+/// Checks if the custom header-field `name` may be sent to other servers than the origin
+pub(crate) fn is_cross_origin_field(name: &str) -> bool {
+	CROSS_ORIGIN_FIELDS.iter().any(|field| field.eq_ignore_ascii_case(name))
+}
+
+/// Validates a custom header-field
+pub(crate) fn check_header_field(name: &str, value: &str) -> Result<(), Error> {
+	if name.is_empty() || name.chars().any(|c| !c.is_ascii_graphic() || c == ':') {
+		throw_err!(ErrorType::InvalidParameter, format!("Invalid header-field-name \"{}\"", name))
+	}
+	if value.chars().any(|c| c == '\r' || c == '\n') {
+		throw_err!(ErrorType::InvalidParameter, format!("Invalid value for the header-field \"{}\"", name))
+	}
+	if PROTECTED_FIELDS.iter().any(|protected| protected.eq_ignore_ascii_case(name)) {
+		throw_err!(ErrorType::InvalidParameter, format!("The header-field \"{}\" is managed internally and cannot be set", name))
+	}
+	Ok(())
+}
+
+/// Sets a header-field in `fields` (replacing an existing field with the same name)
+pub(crate) fn set_header_field(fields: &mut Vec<(String, String)>, name: &str, value: Option<&str>) {
+	fields.retain(|&(ref existing, _)| !existing.eq_ignore_ascii_case(name));
+	if let Some(value) = value { fields.push((name.to_owned(), value.to_owned())) }
+}
+
+/// Options and flags which can be used to configure how a `File` is opened
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+	pub(crate) timeout: std::time::Duration,
+	pub(crate) io_timeout: Option<std::time::Duration>,
+	pub(crate) chunk_count: usize,
+	pub(crate) chunk_size: usize,
+	pub(crate) headers: Vec<(String, String)>,
+	pub(crate) credentials: Option<super::Credentials>,
+	pub(crate) auth_state: std::sync::Arc<super::auth::AuthState>,
+	pub(crate) tls_config: super::TlsConfig,
+	pub(crate) tls_client_config: std::sync::Arc<super::tls::ClientConfigCache>,
+	pub(crate) proxy: super::proxy::ProxyMode,
+	pub(crate) retry_policy: super::RetryPolicy,
+	pub(crate) max_redirects: usize,
+	pub(crate) probe_ranges: bool,
+	pub(crate) prefetch_window: usize,
+	pub(crate) max_connections: usize,
+	pub(crate) disk_cache: Option<(std::path::PathBuf, u64)>,
+	pub(crate) reopen_on_change: bool,
+	pub(crate) cache_policy: Option<super::CachePolicy>
+}
+impl OpenOptions {
+	/// Creates a new set of options with the default configuration
+	pub fn new() -> Self {
+		OpenOptions {
+			timeout: std::time::Duration::from_secs(DEFAULT_TIMEOUT_SECS), io_timeout: None,
+			chunk_count: DEFAULT_CACHE_CHUNK_COUNT, chunk_size: DEFAULT_CACHE_CHUNK_SIZE,
+			headers: vec![("User-Agent".to_owned(), DEFAULT_USER_AGENT.to_owned())], credentials: None, auth_state: Default::default(),
+			tls_config: super::TlsConfig::default(), tls_client_config: Default::default(), proxy: super::proxy::ProxyMode::Environment, retry_policy: super::RetryPolicy::default(), max_redirects: DEFAULT_MAX_REDIRECTS, probe_ranges: false,
+			prefetch_window: DEFAULT_PREFETCH_WINDOW, max_connections: DEFAULT_MAX_CONNECTIONS,
+			disk_cache: None, reopen_on_change: false, cache_policy: None
+		}
+	}
+	
+	/// Sets the timeout for opening the resource (i.e. connecting and receiving the resource-
+	/// information)
+	pub fn timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+		self.timeout = timeout;
+		self
+	}
+	
+	/// Sets the timeout used by the `std::io`-trait-implementations of the `File` (defaults to the
+	/// open-timeout)
+	pub fn io_timeout(&mut self, io_timeout: std::time::Duration) -> &mut Self {
+		self.io_timeout = Some(io_timeout);
+		self
+	}
+	
+	/// Sets the cache-geometry (see `File::adjust_cache_size`)
+	///
+	/// _Note: A `chunk_size` of `0` causes `open` to fail with `ErrorType::InvalidParameter`_
+	pub fn cache_size(&mut self, chunk_count: usize, chunk_size: usize) -> &mut Self {
+		self.chunk_count = chunk_count;
+		self.chunk_size = chunk_size;
+		self
+	}
+	
+	/// Sets a header-field that is sent with every request (replaces an existing field with the
+	/// same name)
+	///
+	/// _Note: The fields `Host`, `Range`, `Content-Length`, `Connection`, `If-Match` and `If-Range`
+	/// are managed internally; trying to set them causes `open` to fail with
+	/// `ErrorType::InvalidParameter`. Like the credentials, all fields except `User-Agent` are sent
+	/// only to the server the `File` was opened on (not to the targets of cross-server-redirects)._
+	pub fn header<T: ToString, U: ToString>(&mut self, name: T, value: U) -> &mut Self {
+		set_header_field(&mut self.headers, &name.to_string(), Some(&value.to_string()));
+		self
+	}
+	
+	/// Sets the `User-Agent`-field (defaults to `http_file/<version>`)
+	pub fn user_agent<T: ToString>(&mut self, user_agent: T) -> &mut Self {
+		self.header("User-Agent", user_agent)
+	}
+	
+	/// Sets the credentials that are used to authenticate every request (overrides the userinfo of
+	/// the URI)
+	///
+	/// _Note: The credentials are sent only to the server the `File` was opened on (not to the
+	/// targets of cross-server-redirects)_
+	pub fn credentials(&mut self, credentials: super::Credentials) -> &mut Self {
+		self.credentials = Some(credentials);
+		self
+	}
+	
+	/// Sets the TLS-configuration used for `https`-connections
+	pub fn tls_config(&mut self, tls_config: super::TlsConfig) -> &mut Self {
+		self.tls_config = tls_config;
+		self.tls_client_config = Default::default();
+		self
+	}
+	
+	/// Connects through `proxy` (overrides the proxy-environment-variables)
+	pub fn proxy(&mut self, proxy: super::Proxy) -> &mut Self {
+		self.proxy = super::proxy::ProxyMode::Explicit(proxy);
+		self
+	}
+	
+	/// Connects directly (ignores the proxy-environment-variables)
+	pub fn no_proxy(&mut self) -> &mut Self {
+		self.proxy = super::proxy::ProxyMode::Disabled;
+		self
+	}
+	
+	/// Returns the proxy that is used to connect to the server of `uri` (if any)
+	///
+	/// _Note: By default, the proxy is taken from `https_proxy`/`http_proxy`/`all_proxy` and
+	/// `no_proxy`_
+	pub(crate) fn proxy_for(&self, uri: &super::URI) -> Option<super::Proxy> {
+		match self.proxy {
+			super::proxy::ProxyMode::Environment => super::Proxy::from_env(uri),
+			super::proxy::ProxyMode::Disabled => None,
+			super::proxy::ProxyMode::Explicit(ref proxy) => Some(proxy.clone())
+		}
+	}
+	
+	/// Sets the policy used to retry failed connects and requests
+	pub fn retry_policy(&mut self, retry_policy: super::RetryPolicy) -> &mut Self {
+		self.retry_policy = retry_policy;
+		self
+	}
+	
+	/// Sets the maximum amount of redirects that are followed for a single request (`0` disables
+	/// redirects)
+	pub fn max_redirects(&mut self, max_redirects: usize) -> &mut Self {
+		self.max_redirects = max_redirects;
+		self
+	}
+	
+	/// If `probe_ranges` is `true` and the server does not send an `Accept-Ranges`-field, a ranged
+	/// `GET` for the first byte is used to check if the server honours ranges anyway (many servers
+	/// and object-stores do); the resource-size is then taken from the `Content-Range`-field
+	///
+	/// _Note: `open` still fails with `ErrorType::Unsupported` if the server answers the probe with
+	/// the entire resource_
+	pub fn probe_ranges(&mut self, probe_ranges: bool) -> &mut Self {
+		self.probe_ranges = probe_ranges;
+		self
+	}
+	
+	/// Sets the amount of chunks that are fetched ahead in the background if a sequential access-
+	/// pattern is detected (`0` disables prefetching, which is useful for random-access-workloads)
+	pub fn prefetch_window(&mut self, prefetch_window: usize) -> &mut Self {
+		self.prefetch_window = prefetch_window;
+		self
+	}
+	
+	/// Sets the maximum amount of concurrent connections per host (used to fetch multiple chunks and
+	/// prefetch in parallel; values smaller than `1` are treated as `1`)
+	pub fn max_connections(&mut self, max_connections: usize) -> &mut Self {
+		self.max_connections = max_connections;
+		self
+	}
+	
+	/// Enables the persistent on-disk chunk-cache in `directory` which is limited to `budget` bytes
+	/// (shared by all resources cached in `directory`)
+	///
+	/// _Note: The disk-cache is only used if the server sends an `ETag`- or `Last-Modified`-field,
+	/// because otherwise we cannot detect if the cached chunks are outdated_
+	pub fn disk_cache<T: AsRef<std::path::Path>>(&mut self, directory: T, budget: u64) -> &mut Self {
+		self.disk_cache = Some((directory.as_ref().to_path_buf(), budget));
+		self
+	}
+	
+	/// If `reopen_on_change` is `true`, the resource is reopened and all cached chunks are discarded
+	/// if the server reports that the resource has changed; otherwise an `ErrorType::ResourceChanged`
+	/// is returned
+	///
+	/// _Note: The pending read is only retried if the resource-size did not change; otherwise the
+	/// read fails with `ErrorType::ResourceChanged` but subsequent reads will use the new resource_
+	pub fn reopen_on_change(&mut self, reopen_on_change: bool) -> &mut Self {
+		self.reopen_on_change = reopen_on_change;
+		self
+	}
+	
+	/// Sets the policy for adaptive cache-resizing (`None` disables adaptive resizing, which is the
+	/// default)
+	pub fn cache_policy(&mut self, cache_policy: Option<super::CachePolicy>) -> &mut Self {
+		self.cache_policy = cache_policy;
+		self
+	}
+	
+	/// Opens the URI with the options specified by `self`
+	///
+	/// _Note: See `File::open` for the retry-behaviour_
+	pub fn open(&self, uri: &str) -> Result<super::File, Error> {
+		super::File::open_with_options(uri, self)
+	}
+}
+impl Default for OpenOptions {
+	fn default() -> Self {
+		OpenOptions::new()
+	}
+}
@@ -0,0 +1,90 @@
+use std;
+use std::collections::HashMap;
+use std::sync::{Mutex, Condvar};
+use super::error::{Error, ErrorType};
+
+/// The pool-state
+struct State {
+	idle: HashMap<String, Vec<super::Connection>>,
+	open: HashMap<String, usize>
+}
+
+/// A pool of keep-alive-connections with a maximum connection-count per host
+pub struct ConnectionPool {
+	max_connections: usize,
+	state: Mutex<State>,
+	released: Condvar
+}
+impl ConnectionPool {
+	/// Creates a new pool that opens up to `max_connections` connections per host
+	pub fn new(max_connections: usize) -> Self {
+		ConnectionPool {
+			max_connections: std::cmp::max(max_connections, 1),
+			state: Mutex::new(State{ idle: HashMap::new(), open: HashMap::new() }),
+			released: Condvar::new()
+		}
+	}
+	
+	/// Returns the maximum connection-count per host
+	pub fn max_connections(&self) -> usize {
+		self.max_connections
+	}
+	
+	/// Checks out a connection to the server of `uri`, calls `f` with it and returns the connection
+	/// to the pool afterwards (or discards it if `f` failed)
+	///
+	/// _Note: If the connection-limit for the host is reached, this function waits until another
+	/// connection is returned or `timeout` is exceeded_
+	pub fn with_connection<T, F>(&self, uri: &mut super::URI, options: &super::OpenOptions, timeout: std::time::Duration, f: F) -> Result<T, Error>
+		where F: FnOnce(&mut super::URI, &mut super::Connection) -> Result<T, Error>
+	{
+		let endpoint = uri.endpoint();
+		let mut connection = self.checkout(uri, options, timeout)?;
+		match f(uri, &mut connection) {
+			Ok(result) => { self.checkin(&endpoint, connection); Ok(result) },
+			Err(error) => { self.release(&endpoint); Err(error) }
+		}
+	}
+	
+	fn checkout(&self, uri: &super::URI, options: &super::OpenOptions, timeout: std::time::Duration) -> Result<super::Connection, Error> {
+		let (timeout_point, endpoint) = (std::time::Instant::now() + timeout, uri.endpoint());
+		
+		let mut state = self.state.lock().unwrap();
+		loop {
+			// Reuse an idle connection
+			if let Some(connection) = state.idle.get_mut(&endpoint).and_then(|idle| idle.pop()) { return Ok(connection) }
+			
+			// Open a new connection if the limit is not reached yet
+			if state.open.get(&endpoint).cloned().unwrap_or(0) < self.max_connections {
+				*state.open.entry(endpoint.clone()).or_insert(0) += 1;
+				drop(state);
+				
+				return match super::Connection::connect(uri, options, super::time_remaining(timeout_point)) {
+					Ok(connection) => Ok(connection),
+					Err(error) => { self.release(&endpoint); Err(error) }
+				}
+			}
+			
+			// Wait until a connection is released
+			let remaining = super::time_remaining(timeout_point);
+			if remaining == std::time::Duration::default() { throw_err!(ErrorType::from(std::io::Error::from(std::io::ErrorKind::TimedOut)), "Timeout while waiting for a pooled connection".to_owned()) }
+			state = self.released.wait_timeout(state, remaining).unwrap().0;
+		}
+	}
+	
+	fn checkin(&self, endpoint: &str, connection: super::Connection) {
+		// The connection might have been replaced by a connection to another host (e.g. due to a
+		// redirect); we don't pool those because they are not accounted for
+		if connection.endpoint() != endpoint { return self.release(endpoint) }
+		
+		let mut state = self.state.lock().unwrap();
+		state.idle.entry(endpoint.to_owned()).or_insert_with(Vec::new).push(connection);
+		self.released.notify_one();
+	}
+	
+	fn release(&self, endpoint: &str) {
+		let mut state = self.state.lock().unwrap();
+		if let Some(open) = state.open.get_mut(endpoint) { *open -= 1 }
+		self.released.notify_one();
+	}
+}
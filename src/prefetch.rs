@@ -0,0 +1,182 @@
+use std;
+use std::sync::{Arc, mpsc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Everything a prefetch-worker needs to fetch chunks independently of the `File`
+pub struct Context {
+	pub uri: super::URI,
+	pub source: super::ChunkSource,
+	pub timeout: std::time::Duration
+}
+
+/// A prefetch-job
+struct Job {
+	aligned_offset: u64,
+	generation: usize
+}
+
+/// A background-thread that fetches chunks over pooled connections
+struct Worker {
+	sender: mpsc::Sender<Job>
+}
+impl Worker {
+	pub fn spawn(context: Context, generation: Arc<AtomicUsize>) -> Option<Self> {
+		let (sender, receiver) = mpsc::channel();
+		let thread = std::thread::Builder::new().name("http_file-prefetch".to_owned())
+			.spawn(move || Worker::run(context, receiver, generation));
+		match thread {
+			Ok(_) => Some(Worker{ sender }),
+			Err(_) => None
+		}
+	}
+	
+	fn run(context: Context, receiver: mpsc::Receiver<Job>, generation: Arc<AtomicUsize>) {
+		let Context{ mut uri, source, timeout } = context;
+		let mut buffer = vec![0u8; source.chunk_size];
+		
+		// Process jobs until the `File` drops the sender
+		while let Ok(job) = receiver.recv() {
+			// Skip outdated jobs and chunks that are already cached
+			if job.generation != generation.load(Ordering::SeqCst) { continue }
+			if source.is_cached(job.aligned_offset) { continue }
+			
+			// Load the chunk (prefetching is best-effort, so errors are not propagated)
+			let length = source.chunk_length(job.aligned_offset);
+			let _ = source.load(&mut uri, job.aligned_offset, &mut buffer[.. length], timeout);
+		}
+	}
+}
+
+
+
+/// Detects sequential access-patterns and schedules the following chunks for background-fetching
+pub struct Prefetcher {
+	window: usize,
+	last_offset: Option<u64>,
+	scheduled_until: u64,
+	generation: Arc<AtomicUsize>,
+	worker: Option<Worker>
+}
+impl Prefetcher {
+	/// Creates a new prefetcher that fetches up to `window` chunks ahead (`0` disables prefetching)
+	pub fn new(window: usize) -> Self {
+		Prefetcher{ window, last_offset: None, scheduled_until: 0, generation: Arc::new(AtomicUsize::new(0)), worker: None }
+	}
+	
+	/// Sets the prefetch-window (`0` disables prefetching and stops the background-thread)
+	pub fn set_window(&mut self, window: usize) {
+		self.window = window;
+		if window == 0 { self.stop() }
+	}
+	
+	/// Returns the prefetch-window
+	pub fn window(&self) -> usize {
+		self.window
+	}
+	
+	/// Records an access to the chunk at `aligned_offset` and returns the offsets of the chunks that
+	/// should be prefetched
+	///
+	/// _Note: The window is limited to `cache_chunk_count - 1` chunks because otherwise the prefetched
+	/// chunks would evict each other or the chunk that is being read_
+	pub fn record_access(&mut self, aligned_offset: u64, chunk_size: usize, size: u64, cache_chunk_count: usize) -> Vec<u64> {
+		let last_offset = std::mem::replace(&mut self.last_offset, Some(aligned_offset));
+		if self.window == 0 { return Vec::new() }
+		
+		// Check the access-pattern
+		match last_offset {
+			Some(last_offset) if last_offset == aligned_offset => return Vec::new(),
+			Some(last_offset) if last_offset + chunk_size as u64 == aligned_offset => (),
+			_ => {
+				// Random access: invalidate all pending jobs
+				self.invalidate();
+				return Vec::new()
+			}
+		}
+		
+		// Compute the chunks that have not been scheduled yet
+		let first = std::cmp::max(aligned_offset + chunk_size as u64, self.scheduled_until);
+		let window = std::cmp::min(self.window, cache_chunk_count.saturating_sub(1));
+		let end = std::cmp::min(aligned_offset + ((window + 1) * chunk_size) as u64, size);
+		let offsets: Vec<u64> = (0..).map(|i| first + i * chunk_size as u64).take_while(|offset| *offset < end).collect();
+		if let Some(last) = offsets.last() { self.scheduled_until = last + chunk_size as u64 }
+		offsets
+	}
+	
+	/// Schedules `offsets` for background-fetching and spawns the worker using `context` if necessary
+	pub fn schedule<F: FnOnce() -> Context>(&mut self, offsets: Vec<u64>, context: F) {
+		// Spawn worker if necessary
+		if self.worker.is_none() { self.worker = Worker::spawn(context(), self.generation.clone()) }
+		
+		// Send jobs (stop the worker if it died)
+		let generation = self.generation.load(Ordering::SeqCst);
+		let sent = match self.worker {
+			Some(ref worker) => offsets.into_iter().all(|aligned_offset| worker.sender.send(Job{ aligned_offset, generation }).is_ok()),
+			None => true
+		};
+		if !sent { self.stop() }
+	}
+	
+	/// Stops the background-thread (e.g. because the cache-geometry changed)
+	pub fn stop(&mut self) {
+		self.invalidate();
+		self.worker = None;
+	}
+	
+	/// Invalidates all pending jobs
+	fn invalidate(&mut self) {
+		self.generation.fetch_add(1, Ordering::SeqCst);
+		self.scheduled_until = 0;
+	}
+}
+impl Drop for Prefetcher {
+	/// Invalidates all pending jobs so that the worker stops without fetching the queued chunks
+	fn drop(&mut self) {
+		self.invalidate()
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::Prefetcher;
+	
+	#[test]
+	fn sequential() {
+		let mut prefetcher = Prefetcher::new(2);
+		assert_eq!(prefetcher.record_access(0, 10, 100, 16), Vec::<u64>::new());
+		assert_eq!(prefetcher.record_access(10, 10, 100, 16), vec![20, 30]);
+		
+		// Only the chunks that have not been scheduled yet are returned
+		assert_eq!(prefetcher.record_access(10, 10, 100, 16), Vec::<u64>::new());
+		assert_eq!(prefetcher.record_access(20, 10, 100, 16), vec![40]);
+		
+		// The window ends at the end of the resource
+		assert_eq!(prefetcher.record_access(30, 10, 45, 16), Vec::<u64>::new());
+	}
+	
+	#[test]
+	fn random() {
+		let mut prefetcher = Prefetcher::new(2);
+		prefetcher.record_access(0, 10, 100, 16);
+		assert_eq!(prefetcher.record_access(50, 10, 100, 16), Vec::<u64>::new());
+		assert_eq!(prefetcher.record_access(60, 10, 100, 16), vec![70, 80]);
+		
+		// A disabled prefetcher never schedules chunks
+		prefetcher.set_window(0);
+		assert_eq!(prefetcher.record_access(70, 10, 100, 16), Vec::<u64>::new());
+	}
+	
+	#[test]
+	fn cache_chunk_count() {
+		// The window is limited by the cache-size
+		let mut prefetcher = Prefetcher::new(8);
+		prefetcher.record_access(0, 10, 1000, 3);
+		assert_eq!(prefetcher.record_access(10, 10, 1000, 3), vec![20, 30]);
+		
+		let mut prefetcher = Prefetcher::new(8);
+		prefetcher.record_access(0, 10, 1000, 1);
+		assert_eq!(prefetcher.record_access(10, 10, 1000, 1), Vec::<u64>::new());
+	}
+}
@@ -0,0 +1,329 @@
+use std;
+use std::sync::Arc;
+
+/// The operation that is retried
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RetryOperation {
+	/// Resolving the address and connecting to the server (or proxy)
+	Connect,
+	/// Reestablishing a broken connection
+	Reconnect,
+	/// Sending a request and receiving the response
+	Request
+}
+
+/// Information about a retry that is passed to the retry-hook
+#[derive(Debug)]
+pub struct RetryEvent<'a> {
+	/// The operation that is retried
+	pub operation: RetryOperation,
+	/// The number of the retry (starting at `1`)
+	pub attempt: usize,
+	/// The delay before the retry
+	pub delay: std::time::Duration,
+	/// The error that caused the retry
+	pub cause: &'a std::io::Error
+}
+
+/// The retry-hook
+pub type RetryHook = Arc<dyn Fn(&RetryEvent) + Send + Sync>;
+
+/// A policy that defines which errors are retried, how often and with which delay
+///
+/// The delay doubles with every retry (starting at `base_delay` and limited to `max_delay`) and is
+/// randomly reduced by up to `jitter` (a fraction between `0.0` and `1.0`) to spread the retries of
+/// concurrent clients. A retry is never scheduled beyond the operation's timeout.
+///
+/// Throttling-responses (`429 Too Many Requests` and `503 Service Unavailable` by default) are
+/// retried too; if the server sends a `Retry-After`-field, its delay is used instead of the backoff.
+#[derive(Clone)]
+pub struct RetryPolicy {
+	max_attempts: usize,
+	base_delay: std::time::Duration,
+	max_delay: std::time::Duration,
+	jitter: f64,
+	retry_on: Vec<std::io::ErrorKind>,
+	retry_on_status: Vec<u16>,
+	hook: Option<RetryHook>
+}
+impl RetryPolicy {
+	/// Creates the default policy (up to 8 retries with a delay from 50ms up to 5s, a jitter of 0.5
+	/// and retries on connection-resets/-aborts, broken pipes, timeouts, unexpected EOFs and the
+	/// HTTP-statuses `429` and `503`)
+	pub fn new() -> Self {
+		RetryPolicy {
+			max_attempts: 8,
+			base_delay: std::time::Duration::from_millis(50), max_delay: std::time::Duration::from_secs(5), jitter: 0.5,
+			retry_on: vec![
+				std::io::ErrorKind::ConnectionReset, std::io::ErrorKind::ConnectionAborted, std::io::ErrorKind::BrokenPipe,
+				std::io::ErrorKind::WouldBlock, std::io::ErrorKind::TimedOut, std::io::ErrorKind::UnexpectedEof
+			],
+			retry_on_status: vec![429, 503],
+			hook: None
+		}
+	}
+	
+	/// Creates a policy that never retries
+	pub fn never() -> Self {
+		let mut policy = RetryPolicy::new();
+		policy.max_attempts(0);
+		policy
+	}
+	
+	/// Sets the maximum amount of retries per operation (`0` disables retries)
+	pub fn max_attempts(&mut self, max_attempts: usize) -> &mut Self {
+		self.max_attempts = max_attempts;
+		self
+	}
+	
+	/// Sets the delay before the first retry and the maximum delay
+	pub fn delay(&mut self, base_delay: std::time::Duration, max_delay: std::time::Duration) -> &mut Self {
+		self.base_delay = base_delay;
+		self.max_delay = std::cmp::max(base_delay, max_delay);
+		self
+	}
+	
+	/// Sets the jitter (the fraction by which the delay is randomly reduced; clamped to `0.0..=1.0`)
+	pub fn jitter(&mut self, jitter: f64) -> &mut Self {
+		self.jitter = if jitter.is_nan() { 0.0 } else { jitter.max(0.0).min(1.0) };
+		self
+	}
+	
+	/// Sets whether errors of `kind` are retried
+	pub fn retry_on(&mut self, kind: std::io::ErrorKind, retry: bool) -> &mut Self {
+		self.retry_on.retain(|retry_on| *retry_on != kind);
+		if retry { self.retry_on.push(kind) }
+		self
+	}
+	
+	/// Sets whether responses with the HTTP-status `status` are retried
+	pub fn retry_on_status(&mut self, status: u16, retry: bool) -> &mut Self {
+		self.retry_on_status.retain(|retry_on| *retry_on != status);
+		if retry { self.retry_on_status.push(status) }
+		self
+	}
+	
+	/// Sets a hook that is called before every retry (e.g. for logging)
+	pub fn on_retry<F: Fn(&RetryEvent) + Send + Sync + 'static>(&mut self, hook: F) -> &mut Self {
+		self.hook = Some(Arc::new(hook));
+		self
+	}
+	
+	/// Checks if errors of `kind` are retried
+	pub fn is_retryable(&self, kind: std::io::ErrorKind) -> bool {
+		self.retry_on.contains(&kind)
+	}
+	
+	/// Checks if responses with the HTTP-status `status` are retried
+	pub fn is_retryable_status(&self, status: u16) -> bool {
+		self.retry_on_status.contains(&status)
+	}
+	
+	/// Creates the retry-state for a single operation
+	pub(crate) fn retrier<'a>(&'a self, operation: RetryOperation) -> Retrier<'a> {
+		Retrier{ policy: self, operation, attempt: 0 }
+	}
+	
+	/// Counts the next attempt of `operation` and returns the delay before it (`delay` or the backoff-
+	/// delay) after calling the hook; returns `None` if the attempts are exhausted or the delay would
+	/// exceed `timeout_point`
+	///
+	/// _Note: This is used by `Retrier` and by the asynchronous operations (which must not block
+	/// while waiting)_
+	pub(crate) fn next_delay(&self, operation: RetryOperation, attempt: &mut usize, delay: Option<std::time::Duration>, cause: &std::io::Error, timeout_point: std::time::Instant) -> Option<std::time::Duration> {
+		if *attempt >= self.max_attempts { return None }
+		*attempt += 1;
+		
+		// Compute the delay and check the timeout
+		let delay = delay.unwrap_or_else(|| self.backoff(*attempt));
+		if delay >= super::time_remaining(timeout_point) { return None }
+		
+		// Call the hook
+		if let Some(ref hook) = self.hook {
+			hook(&RetryEvent{ operation, attempt: *attempt, delay, cause })
+		}
+		Some(delay)
+	}
+	
+	/// Computes the delay before the `attempt`th retry (including the jitter)
+	fn backoff(&self, attempt: usize) -> std::time::Duration {
+		let exponent = std::cmp::min(attempt.saturating_sub(1), 31) as u32;
+		let delay = self.base_delay.checked_mul(1 << exponent).unwrap_or(self.max_delay);
+		let delay = std::cmp::min(delay, self.max_delay);
+		
+		// Apply the jitter (huge delays that cannot be represented as float are used as is)
+		let jittered = delay.as_secs_f64() * (1.0 - self.jitter * random_fraction());
+		std::time::Duration::try_from_secs_f64(jittered).map(|jittered| std::cmp::min(jittered, delay)).unwrap_or(delay)
+	}
+}
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		RetryPolicy::new()
+	}
+}
+impl std::fmt::Debug for RetryPolicy {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.debug_struct("RetryPolicy")
+			.field("max_attempts", &self.max_attempts)
+			.field("base_delay", &self.base_delay)
+			.field("max_delay", &self.max_delay)
+			.field("jitter", &self.jitter)
+			.field("retry_on", &self.retry_on)
+			.field("retry_on_status", &self.retry_on_status)
+			.field("hook", &self.hook.is_some())
+			.finish()
+	}
+}
+
+/// Returns a random number between `0.0` and `1.0`
+fn random_fraction() -> f64 {
+	use std::hash::{BuildHasher, Hash, Hasher};
+	static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+	
+	// `RandomState` is randomly seeded, so hashing a counter yields random numbers
+	let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+	COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed).hash(&mut hasher);
+	(hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+
+
+/// The retry-state of a single operation
+pub(crate) struct Retrier<'a> {
+	policy: &'a RetryPolicy,
+	operation: RetryOperation,
+	attempt: usize
+}
+impl<'a> Retrier<'a> {
+	/// Checks if the operation should be retried after `error` and waits for the backoff-delay if
+	/// so; returns `false` if the error is not retryable, the attempts are exhausted or the delay
+	/// would exceed `timeout_point`
+	pub fn retry(&mut self, error: &std::io::Error, timeout_point: std::time::Instant) -> bool {
+		if !self.policy.is_retryable(error.kind()) { return false }
+		self.wait(None, error, timeout_point)
+	}
+	
+	/// Checks if a request that was throttled by the server should be retried and waits for
+	/// `retry_after` (or the backoff-delay if the server did not send a `Retry-After`-field) if so;
+	/// returns `false` if the attempts are exhausted or the delay would exceed `timeout_point`
+	///
+	/// _Note: The caller must check that the status is retryable_
+	pub fn throttled(&mut self, retry_after: Option<std::time::Duration>, cause: &std::io::Error, timeout_point: std::time::Instant) -> bool {
+		self.wait(retry_after, cause, timeout_point)
+	}
+	
+	/// Waits for `delay` (or the backoff-delay) before the next attempt
+	fn wait(&mut self, delay: Option<std::time::Duration>, cause: &std::io::Error, timeout_point: std::time::Instant) -> bool {
+		match self.policy.next_delay(self.operation, &mut self.attempt, delay, cause, timeout_point) {
+			Some(delay) => { std::thread::sleep(delay); true },
+			None => false
+		}
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use std;
+	use std::sync::{Arc, Mutex};
+	use super::{RetryOperation, RetryPolicy};
+	
+	/// Creates a policy without jitter
+	fn policy(base_delay: std::time::Duration, max_delay: std::time::Duration) -> RetryPolicy {
+		let mut policy = RetryPolicy::new();
+		policy.delay(base_delay, max_delay).jitter(0.0);
+		policy
+	}
+	
+	#[test]
+	fn backoff() {
+		let policy = policy(std::time::Duration::from_millis(100), std::time::Duration::from_secs(1));
+		let delays: Vec<u128> = (0 ..= 6).map(|attempt| policy.backoff(attempt).as_millis()).collect();
+		assert_eq!(delays, vec![100, 100, 200, 400, 800, 1000, 1000]);
+		assert_eq!(policy.backoff(usize::MAX), std::time::Duration::from_secs(1));
+	}
+	
+	#[test]
+	fn backoff_overflow() {
+		let policy = policy(std::time::Duration::from_secs(u64::MAX / 2), std::time::Duration::from_secs(u64::MAX));
+		assert_eq!(policy.backoff(1), std::time::Duration::from_secs(u64::MAX / 2));
+		assert_eq!(policy.backoff(3), std::time::Duration::from_secs(u64::MAX));
+		assert_eq!(policy.backoff(64), std::time::Duration::from_secs(u64::MAX));
+	}
+	
+	#[test]
+	fn backoff_jitter() {
+		let mut policy = policy(std::time::Duration::from_millis(100), std::time::Duration::from_secs(1));
+		policy.jitter(0.5);
+		for _ in 0 .. 1000 {
+			let delay = policy.backoff(3);
+			assert!(delay >= std::time::Duration::from_millis(200) && delay <= std::time::Duration::from_millis(400));
+		}
+		
+		// The jitter is clamped
+		policy.jitter(2.0);
+		assert!(policy.backoff(3) <= std::time::Duration::from_millis(400));
+		policy.jitter(f64::NAN);
+		assert_eq!(policy.backoff(3), std::time::Duration::from_millis(400));
+	}
+	
+	#[test]
+	fn attempts() {
+		let mut policy = policy(std::time::Duration::from_millis(1), std::time::Duration::from_millis(1));
+		policy.max_attempts(2);
+		let timeout_point = std::time::Instant::now() + std::time::Duration::from_secs(60);
+		
+		// Non-retryable errors do not count as attempt
+		let mut retrier = policy.retrier(RetryOperation::Request);
+		assert!(!retrier.retry(&std::io::Error::from(std::io::ErrorKind::NotFound), timeout_point));
+		
+		let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+		assert!(retrier.retry(&reset, timeout_point));
+		assert!(retrier.throttled(None, &reset, timeout_point));
+		assert!(!retrier.retry(&reset, timeout_point));
+		
+		// No retries at all
+		let policy = RetryPolicy::never();
+		assert!(!policy.retrier(RetryOperation::Connect).retry(&reset, timeout_point));
+	}
+	
+	#[test]
+	fn deadline() {
+		let policy = policy(std::time::Duration::from_secs(10), std::time::Duration::from_secs(10));
+		let timeout_point = std::time::Instant::now() + std::time::Duration::from_secs(1);
+		let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+		
+		// The backoff-delay and the `Retry-After`-delay must not exceed the deadline
+		let start = std::time::Instant::now();
+		assert!(!policy.retrier(RetryOperation::Request).retry(&reset, timeout_point));
+		assert!(!policy.retrier(RetryOperation::Request).throttled(Some(std::time::Duration::from_secs(2)), &reset, timeout_point));
+		assert!(start.elapsed() < std::time::Duration::from_secs(1));
+		
+		assert!(policy.retrier(RetryOperation::Request).throttled(Some(std::time::Duration::from_millis(1)), &reset, timeout_point));
+	}
+	
+	#[test]
+	fn hook() {
+		let events = Arc::new(Mutex::new(Vec::new()));
+		let mut policy = policy(std::time::Duration::from_millis(1), std::time::Duration::from_millis(2));
+		let events_ = events.clone();
+		policy.max_attempts(3).on_retry(move |event| {
+			events_.lock().unwrap().push((event.operation, event.attempt, event.delay, event.cause.kind()))
+		});
+		
+		let timeout_point = std::time::Instant::now() + std::time::Duration::from_secs(60);
+		let mut retrier = policy.retrier(RetryOperation::Reconnect);
+		assert!(retrier.retry(&std::io::Error::from(std::io::ErrorKind::BrokenPipe), timeout_point));
+		assert!(retrier.retry(&std::io::Error::from(std::io::ErrorKind::TimedOut), timeout_point));
+		assert!(retrier.throttled(Some(std::time::Duration::from_millis(5)), &std::io::Error::from(std::io::ErrorKind::Other), timeout_point));
+		assert!(!retrier.retry(&std::io::Error::from(std::io::ErrorKind::TimedOut), timeout_point));
+		
+		let millis = std::time::Duration::from_millis;
+		assert_eq!(*events.lock().unwrap(), vec![
+			(RetryOperation::Reconnect, 1, millis(1), std::io::ErrorKind::BrokenPipe),
+			(RetryOperation::Reconnect, 2, millis(2), std::io::ErrorKind::TimedOut),
+			(RetryOperation::Reconnect, 3, millis(5), std::io::ErrorKind::Other)
+		]);
+	}
+}
@@ -0,0 +1,156 @@
+use std;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, Condvar};
+use super::error::{Error, ErrorType};
+
+/// The state shared by all clones of a `SharedFile`
+pub(crate) struct Inner {
+	pub(crate) source: super::ChunkSource,
+	pub(crate) name: String,
+	pub(crate) uri: Mutex<super::URI>,
+	
+	pub(crate) in_flight: Mutex<HashSet<u64>>,
+	pub(crate) fetched: Condvar
+}
+
+/// Removes a chunk from the in-flight-set and wakes up the waiting readers (also if the fetch
+/// failed)
+struct InFlight<'a> {
+	inner: &'a Inner,
+	aligned_offset: u64
+}
+impl<'a> Drop for InFlight<'a> {
+	fn drop(&mut self) {
+		self.inner.in_flight.lock().unwrap().remove(&self.aligned_offset);
+		self.inner.fetched.notify_all();
+	}
+}
+
+/// A thread-safe handle to a HTTP-resource that can be cloned and shared across threads
+///
+/// All clones share the connection-pool and the caches; concurrent misses are fetched over
+/// separate connections and a chunk that is already being fetched by another reader is not
+/// fetched twice.
+///
+/// _Note: Unlike `File`, a `SharedFile` never reopens a changed resource; reads fail with
+/// `ErrorType::ResourceChanged` instead_
+#[derive(Clone)]
+pub struct SharedFile {
+	inner: Arc<Inner>
+}
+impl SharedFile {
+	pub(crate) fn from_inner(inner: Inner) -> Self {
+		SharedFile{ inner: Arc::new(inner) }
+	}
+	
+	/// Returns the chunk-source
+	#[cfg(feature = "tokio")]
+	pub(crate) fn source(&self) -> &super::ChunkSource {
+		&self.inner.source
+	}
+	
+	/// Returns the effective URI
+	#[cfg(feature = "tokio")]
+	pub(crate) fn uri(&self) -> super::URI {
+		self.inner.uri.lock().unwrap().clone()
+	}
+	
+	/// Remembers the effective URI (e.g. after a redirect)
+	#[cfg(feature = "tokio")]
+	pub(crate) fn set_uri(&self, uri: super::URI) {
+		*self.inner.uri.lock().unwrap() = uri
+	}
+	
+	/// Returns the file-size
+	pub fn size(&self) -> u64 {
+		self.inner.source.resource.size
+	}
+	
+	/// Returns the file-name
+	pub fn name(&self) -> &str {
+		&self.inner.name
+	}
+	
+	/// Returns the effective URL (i.e. the URL after all redirects have been followed)
+	pub fn effective_url(&self) -> String {
+		self.inner.uri.lock().unwrap().url.clone()
+	}
+	
+	/// Returns the `ETag` of the resource (if any)
+	pub fn etag(&self) -> Option<&str> {
+		self.inner.source.resource.etag.as_ref().map(|etag| etag.as_str())
+	}
+	
+	/// Returns the `Last-Modified`-date of the resource (if any)
+	pub fn last_modified(&self) -> Option<&str> {
+		self.inner.source.resource.last_modified.as_ref().map(|last_modified| last_modified.as_str())
+	}
+	
+	/// Reads `buffer.len()` bytes at `offset` into `buffer` and returns the amount of bytes read (which
+	/// is only smaller than `buffer.len()` at the end of the resource)
+	///
+	/// _Note: See `File::open` for the retry-behaviour_
+	pub fn read_at(&self, buffer: &mut[u8], offset: u64, timeout: std::time::Duration) -> Result<usize, Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		let (size, chunk_size) = (self.inner.source.resource.size, self.inner.source.chunk_size);
+		
+		// Check if there is anything to read
+		if offset >= size || buffer.len() == 0 { return Ok(0) }
+		let to_read = std::cmp::min(buffer.len(), (size - offset) as usize);
+		
+		// Copy the chunks
+		let (mut buffer_pos, mut position) = (0, offset);
+		while buffer_pos < to_read {
+			let aligned_offset = (position / chunk_size as u64) * chunk_size as u64;
+			let skip = (position - aligned_offset) as usize;
+			let to_copy = std::cmp::min(chunk_size - skip, to_read - buffer_pos);
+			
+			self.read_chunk_into(aligned_offset, skip, &mut buffer[buffer_pos .. buffer_pos + to_copy], timeout_point)?;
+			buffer_pos += to_copy;
+			position += to_copy as u64;
+		}
+		Ok(to_read)
+	}
+	
+	/// Copies the part of the chunk at `aligned_offset` that starts at `skip` into `buffer`
+	fn read_chunk_into(&self, aligned_offset: u64, skip: usize, buffer: &mut[u8], timeout_point: std::time::Instant) -> Result<(), Error> {
+		let inner = &self.inner;
+		loop {
+			// Copy the chunk from the cache
+			if inner.source.read_cached(aligned_offset, skip, buffer) { return Ok(()) }
+			
+			// Claim the chunk or wait until the reader that claimed it is done
+			let mut in_flight = inner.in_flight.lock().unwrap();
+			if in_flight.insert(aligned_offset) { break }
+			while in_flight.contains(&aligned_offset) {
+				let remaining = super::time_remaining(timeout_point);
+				if remaining == std::time::Duration::default() { throw_err!(ErrorType::from(std::io::Error::from(std::io::ErrorKind::TimedOut)), "Timeout while waiting for a concurrent fetch".to_owned()) }
+				in_flight = inner.fetched.wait_timeout(in_flight, remaining).unwrap().0;
+			}
+		}
+		let _in_flight = InFlight{ inner, aligned_offset };
+		
+		// Load the chunk directly into `buffer` if it covers the entire chunk
+		let length = inner.source.chunk_length(aligned_offset);
+		if skip == 0 && buffer.len() == length { return self.load_chunk(aligned_offset, buffer, timeout_point) }
+		
+		let mut chunk = vec![0u8; length];
+		self.load_chunk(aligned_offset, &mut chunk, timeout_point)?;
+		buffer.copy_from_slice(&chunk[skip .. skip + buffer.len()]);
+		Ok(())
+	}
+	
+	/// Loads the chunk at `aligned_offset` into `buffer` (see `ChunkSource::load`) and remembers the
+	/// effective URI
+	fn load_chunk(&self, aligned_offset: u64, buffer: &mut[u8], timeout_point: std::time::Instant) -> Result<(), Error> {
+		let mut uri = self.inner.uri.lock().unwrap().clone();
+		self.inner.source.load(&mut uri, aligned_offset, buffer, super::time_remaining(timeout_point))?;
+		*self.inner.uri.lock().unwrap() = uri;
+		Ok(())
+	}
+}
+impl From<super::File> for SharedFile {
+	fn from(file: super::File) -> Self {
+		file.into_shared()
+	}
+}
@@ -0,0 +1,77 @@
+use std;
+use std::sync::{Arc, Mutex};
+use super::error::Error;
+
+/// The resource, the connection-pool and the caches shared by a `File`, it's prefetch-worker and
+/// `SharedFile`s
+///
+/// All chunks are loaded through `load`, which checks the disk-cache before fetching the chunk and
+/// stores fetched chunks in both caches.
+#[derive(Clone)]
+pub struct ChunkSource {
+	pub resource: super::ResourceInfo,
+	pub options: super::OpenOptions,
+	pub pool: Arc<super::ConnectionPool>,
+	pub chunk_size: usize,
+	pub cache: Arc<Mutex<super::CacheDB>>,
+	pub disk_cache: Option<Arc<super::DiskCache>>
+}
+impl ChunkSource {
+	/// Returns the length of the chunk at `aligned_offset` (the last chunk might be smaller than the
+	/// chunk-size)
+	pub fn chunk_length(&self, aligned_offset: u64) -> usize {
+		std::cmp::min((self.resource.size - aligned_offset) as usize, self.chunk_size)
+	}
+	
+	/// Checks if the chunk at `aligned_offset` is in the in-memory-cache
+	pub fn is_cached(&self, aligned_offset: u64) -> bool {
+		self.cache.lock().unwrap().contains(aligned_offset)
+	}
+	
+	/// Copies the part of the chunk at `aligned_offset` that starts at `skip` from the in-memory-cache
+	/// into `buffer`, records the access and returns whether the chunk was cached or not
+	pub fn read_cached(&self, aligned_offset: u64, skip: usize, buffer: &mut[u8]) -> bool {
+		let mut cache = self.cache.lock().unwrap();
+		let hit = cache.contains(aligned_offset);
+		if hit { buffer.copy_from_slice(&cache.get(aligned_offset)[skip .. skip + buffer.len()]) }
+		
+		// Record the access after copying because the cache might shrink
+		cache.record_access(hit);
+		hit
+	}
+	
+	/// Loads the chunk at `aligned_offset` from the disk-cache into `buffer` (which must have the
+	/// chunk-length), inserts it into the in-memory-cache and returns whether the chunk was cached or
+	/// not
+	pub fn load_from_disk(&self, aligned_offset: u64, buffer: &mut[u8]) -> bool {
+		match self.disk_cache {
+			Some(ref disk_cache) if disk_cache.load(aligned_offset, buffer) => {
+				self.cache.lock().unwrap().insert(buffer, aligned_offset);
+				true
+			},
+			_ => false
+		}
+	}
+	
+	/// Loads the chunk at `aligned_offset` from the disk-cache or the server into `buffer` (which
+	/// must have the chunk-length) and inserts it into the caches
+	///
+	/// _Note: `uri` is updated if the request is redirected_
+	pub fn load(&self, uri: &mut super::URI, aligned_offset: u64, buffer: &mut[u8], timeout: std::time::Duration) -> Result<(), Error> {
+		if self.load_from_disk(aligned_offset, buffer) { return Ok(()) }
+		
+		// Fetch the chunk
+		let timeout_point = std::time::Instant::now() + timeout;
+		self.pool.with_connection(uri, &self.options, timeout, |uri, connection| {
+			super::http_action::fetch_chunk(uri, connection, &self.options, &self.resource, buffer, aligned_offset, super::time_remaining(timeout_point))
+		})?;
+		self.insert(aligned_offset, buffer);
+		Ok(())
+	}
+	
+	/// Inserts the chunk `data` at `aligned_offset` into the disk-cache and the in-memory-cache
+	pub fn insert(&self, aligned_offset: u64, data: &[u8]) {
+		if let Some(ref disk_cache) = self.disk_cache { disk_cache.store(aligned_offset, data) }
+		self.cache.lock().unwrap().insert(data, aligned_offset);
+	}
+}
@@ -0,0 +1,312 @@
+use std;
+use std::io::{Read, Write};
+use std::convert::TryFrom;
+use super::error::{Error, ErrorType};
+extern crate rustls;
+extern crate rustls_pemfile;
+extern crate webpki_roots;
+
+/// The TLS-configuration used for `https`-connections
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+	use_builtin_roots: bool,
+	ca_certificates: Vec<Vec<u8>>,
+	accept_invalid_certs: bool
+}
+impl TlsConfig {
+	/// Creates a new TLS-configuration that verifies the server-certificates against the built-in
+	/// Mozilla-root-store
+	pub fn new() -> Self {
+		TlsConfig{ use_builtin_roots: true, ca_certificates: Vec::new(), accept_invalid_certs: false }
+	}
+	
+	/// Enables or disables the built-in Mozilla-root-store
+	///
+	/// _Note: If you disable the built-in root-store, you must add your own CA-certificates using
+	/// `add_ca_certificates` or `add_ca_bundle`_
+	pub fn set_use_builtin_roots(&mut self, use_builtin_roots: bool) {
+		self.use_builtin_roots = use_builtin_roots
+	}
+	
+	/// Adds all PEM-encoded CA-certificates in `pem` to the root-store
+	pub fn add_ca_certificates(&mut self, pem: &[u8]) -> Result<(), Error> {
+		let certificates = try_err!(rustls_pemfile::certs(&mut std::io::Cursor::new(pem)), "Failed to parse the PEM-encoded certificates".to_owned());
+		if certificates.is_empty() { throw_err!(ErrorType::InvalidData, "No PEM-encoded certificate found".to_owned()) }
+		
+		self.ca_certificates.extend(certificates);
+		Ok(())
+	}
+	
+	/// Adds all CA-certificates in the PEM-encoded bundle at `path` to the root-store
+	pub fn add_ca_bundle<T: AsRef<std::path::Path>>(&mut self, path: T) -> Result<(), Error> {
+		let mut pem = Vec::new();
+		try_err!(std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut pem)), "Failed to read the CA-bundle".to_owned());
+		self.add_ca_certificates(&pem)
+	}
+	
+	/// Disables the server-certificate-verification if `accept_invalid_certs` is `true`
+	///
+	/// _Warning: This makes the connection vulnerable to man-in-the-middle-attacks; only use this
+	/// for testing-purposes (e.g. with a self-signed certificate)_
+	pub fn set_danger_accept_invalid_certs(&mut self, accept_invalid_certs: bool) {
+		self.accept_invalid_certs = accept_invalid_certs
+	}
+	
+	/// Builds the `rustls`-client-configuration
+	pub fn client_config(&self) -> Result<std::sync::Arc<rustls::ClientConfig>, Error> {
+		// Build root-store
+		let mut root_store = rustls::RootCertStore::empty();
+		if self.use_builtin_roots {
+			root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+				rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(anchor.subject, anchor.spki, anchor.name_constraints)
+			}));
+		}
+		for certificate in self.ca_certificates.iter() {
+			if let Err(error) = root_store.add(&rustls::Certificate(certificate.clone())) {
+				throw_err!(ErrorType::InvalidData, format!("Invalid CA-certificate: {}", error))
+			}
+		}
+		
+		// Create config
+		let mut config = rustls::ClientConfig::builder()
+			.with_safe_defaults()
+			.with_root_certificates(root_store)
+			.with_no_client_auth();
+		if self.accept_invalid_certs { config.dangerous().set_certificate_verifier(std::sync::Arc::new(AcceptAnyCertificate)) }
+		Ok(std::sync::Arc::new(config))
+	}
+}
+impl Default for TlsConfig {
+	fn default() -> Self {
+		TlsConfig::new()
+	}
+}
+
+
+
+/// The `rustls`-client-configuration of a `File` which is built on the first `https`-connect and
+/// shared by all subsequent connections (building the root-store is expensive)
+#[derive(Default)]
+pub struct ClientConfigCache {
+	config: std::sync::OnceLock<std::sync::Arc<rustls::ClientConfig>>
+}
+impl ClientConfigCache {
+	/// Returns the client-configuration for `tls_config` (building it if necessary)
+	pub fn get(&self, tls_config: &TlsConfig) -> Result<std::sync::Arc<rustls::ClientConfig>, Error> {
+		if let Some(config) = self.config.get() { return Ok(config.clone()) }
+		let config = tls_config.client_config()?;
+		Ok(self.config.get_or_init(|| config).clone())
+	}
+}
+impl std::fmt::Debug for ClientConfigCache {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.debug_struct("ClientConfigCache").field("initialized", &self.config.get().is_some()).finish()
+	}
+}
+
+
+
+/// A certificate-verifier that accepts every certificate
+struct AcceptAnyCertificate;
+impl rustls::client::ServerCertVerifier for AcceptAnyCertificate {
+	fn verify_server_cert(&self, _: &rustls::Certificate, _: &[rustls::Certificate], _: &rustls::ServerName, _: &mut dyn Iterator<Item = &[u8]>, _: &[u8], _: std::time::SystemTime) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+		Ok(rustls::client::ServerCertVerified::assertion())
+	}
+}
+
+
+
+/// A TLS-stream over a TCP-socket
+pub struct TlsStream {
+	stream: rustls::StreamOwned<rustls::ClientConnection, std::net::TcpStream>
+}
+impl TlsStream {
+	/// Connects to `address` and performs the TLS-handshake using `host` as server-name
+	pub fn connect(address: std::net::SocketAddr, host: &str, config: std::sync::Arc<rustls::ClientConfig>, timeout: std::time::Duration) -> Result<Self, std::io::Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		let socket = std::net::TcpStream::connect_timeout(&address, timeout)?;
+		TlsStream::handshake(socket, host, config, super::time_remaining(timeout_point))
+	}
+	
+	/// Performs the TLS-handshake over an already connected `socket` (e.g. a proxy-tunnel) using
+	/// `host` as server-name
+	pub fn handshake(socket: std::net::TcpStream, host: &str, config: std::sync::Arc<rustls::ClientConfig>, timeout: std::time::Duration) -> Result<Self, std::io::Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		
+		// Create TLS-session
+		let server_name = match rustls::ServerName::try_from(host) {
+			Ok(server_name) => server_name,
+			Err(error) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, error))
+		};
+		let session = match rustls::ClientConnection::new(config, server_name) {
+			Ok(session) => session,
+			Err(error) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+		};
+		
+		// Perform handshake
+		let mut tls_stream = TlsStream{ stream: rustls::StreamOwned::new(session, socket) };
+		while tls_stream.stream.conn.is_handshaking() {
+			tls_stream.set_timeout(timeout_point)?;
+			tls_stream.stream.conn.complete_io(&mut tls_stream.stream.sock)?;
+		}
+		Ok(tls_stream)
+	}
+	
+	/// Sets the socket-timeouts so that no operation exceeds `timeout_point`
+	fn set_timeout(&mut self, timeout_point: std::time::Instant) -> Result<(), std::io::Error> {
+		let remaining = super::time_remaining(timeout_point);
+		if remaining == std::time::Duration::default() { return Err(std::io::Error::from(std::io::ErrorKind::TimedOut)) }
+		
+		self.stream.sock.set_read_timeout(Some(remaining))?;
+		self.stream.sock.set_write_timeout(Some(remaining))
+	}
+}
+impl super::connection::Transport for TlsStream {
+	fn read(&mut self, buffer: &mut[u8], buffer_pos: &mut usize, timeout: std::time::Duration) -> Result<(), std::io::Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		
+		// Read until the buffer is filled
+		while *buffer_pos < buffer.len() {
+			self.set_timeout(timeout_point)?;
+			match self.stream.read(&mut buffer[*buffer_pos..]) {
+				Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)),
+				Ok(read) => *buffer_pos += read,
+				Err(ref error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+				Err(error) => return Err(error)
+			}
+		}
+		Ok(())
+	}
+	
+	fn read_until(&mut self, buffer: &mut[u8], buffer_pos: &mut usize, pattern: &[u8], timeout: std::time::Duration) -> Result<(), std::io::Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		
+		// Read byte-by-byte to avoid consuming data behind `pattern`; most bytes are served from the
+		// plaintext-buffer of `rustls`, so the socket-timeouts are set only once
+		self.set_timeout(timeout_point)?;
+		while *buffer_pos < buffer.len() {
+			if super::time_remaining(timeout_point) == std::time::Duration::default() { return Err(std::io::Error::from(std::io::ErrorKind::TimedOut)) }
+			match self.stream.read(&mut buffer[*buffer_pos .. *buffer_pos + 1]) {
+				Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)),
+				Ok(read) => *buffer_pos += read,
+				Err(ref error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+				Err(error) => return Err(error)
+			}
+			if buffer[.. *buffer_pos].ends_with(pattern) { return Ok(()) }
+		}
+		Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Pattern not found within buffer"))
+	}
+	
+	fn write(&mut self, data: &[u8], data_pos: &mut usize, timeout: std::time::Duration) -> Result<(), std::io::Error> {
+		let timeout_point = std::time::Instant::now() + timeout;
+		
+		// Write all data and flush the TLS-session
+		while *data_pos < data.len() {
+			self.set_timeout(timeout_point)?;
+			match self.stream.write(&data[*data_pos..]) {
+				Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::WriteZero)),
+				Ok(written) => *data_pos += written,
+				Err(ref error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+				Err(error) => return Err(error)
+			}
+		}
+		self.set_timeout(timeout_point)?;
+		self.stream.flush()
+	}
+	
+	fn shutdown(&mut self) {
+		self.stream.conn.send_close_notify();
+		let _ = self.stream.flush();
+		let _ = self.stream.sock.shutdown(std::net::Shutdown::Both);
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use std;
+	use std::io::{Read, Write};
+	use super::rustls;
+	use super::rustls_pemfile;
+	use super::super::connection::Transport;
+	use super::{TlsConfig, TlsStream, ClientConfigCache};
+	
+	static CA: &[u8] = include_bytes!("../testdata/tls-ca.pem");
+	static CERTIFICATE_CHAIN: &[u8] = include_bytes!("../testdata/tls-localhost.pem");
+	static PRIVATE_KEY: &[u8] = include_bytes!("../testdata/tls-localhost-key.pem");
+	static RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nHello World";
+	
+	/// Starts a local TLS-server with a certificate for `localhost` (issued by the test-CA) that answers
+	/// a single request with `RESPONSE`
+	fn serve_once() -> std::net::SocketAddr {
+		let certificates = rustls_pemfile::certs(&mut std::io::Cursor::new(CERTIFICATE_CHAIN)).unwrap()
+			.into_iter().map(rustls::Certificate).collect();
+		let key = rustls::PrivateKey(rustls_pemfile::pkcs8_private_keys(&mut std::io::Cursor::new(PRIVATE_KEY)).unwrap().remove(0));
+		let config = rustls::ServerConfig::builder().with_safe_defaults().with_no_client_auth()
+			.with_single_cert(certificates, key).unwrap();
+		
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		std::thread::spawn(move || {
+			let socket = listener.accept().unwrap().0;
+			let session = rustls::ServerConnection::new(std::sync::Arc::new(config)).unwrap();
+			let mut stream = rustls::StreamOwned::new(session, socket);
+			
+			// Receive the request-header and send the response (errors mean the client hung up)
+			let mut request = Vec::new();
+			while !request.ends_with(b"\r\n\r\n") {
+				let mut byte = [0u8];
+				if stream.read_exact(&mut byte).is_err() { return }
+				request.push(byte[0]);
+			}
+			let _ = stream.write_all(RESPONSE).and_then(|_| stream.flush());
+		});
+		address
+	}
+	
+	/// Sends a request over a new TLS-connection and returns the response-header and -body
+	fn request(tls_config: &TlsConfig) -> Result<(Vec<u8>, Vec<u8>), std::io::Error> {
+		let (address, timeout) = (serve_once(), std::time::Duration::from_secs(10));
+		let mut stream = TlsStream::connect(address, "localhost", tls_config.client_config().unwrap(), timeout)?;
+		stream.write(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n", &mut 0, timeout)?;
+		
+		let (mut header, mut header_pos) = (vec![0u8; 1024], 0);
+		stream.read_until(&mut header, &mut header_pos, b"\r\n\r\n", timeout)?;
+		header.truncate(header_pos);
+		
+		let mut body = vec![0u8; 11];
+		stream.read(&mut body, &mut 0, timeout)?;
+		Ok((header, body))
+	}
+	
+	#[test]
+	fn custom_ca() {
+		let mut tls_config = TlsConfig::new();
+		tls_config.set_use_builtin_roots(false);
+		tls_config.add_ca_certificates(CA).unwrap();
+		
+		let (header, body) = request(&tls_config).unwrap();
+		assert_eq!(header, b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n");
+		assert_eq!(body, b"Hello World");
+	}
+	
+	#[test]
+	fn unknown_ca() {
+		assert!(request(&TlsConfig::new()).is_err());
+	}
+	
+	#[test]
+	fn accept_invalid_certs() {
+		let mut tls_config = TlsConfig::new();
+		tls_config.set_danger_accept_invalid_certs(true);
+		assert_eq!(request(&tls_config).unwrap().1, b"Hello World");
+	}
+	
+	#[test]
+	fn client_config_is_built_once() {
+		let (cache, tls_config) = (ClientConfigCache::default(), TlsConfig::new());
+		let config = cache.get(&tls_config).unwrap();
+		assert!(std::sync::Arc::ptr_eq(&config, &cache.get(&tls_config).unwrap()));
+	}
+}